@@ -4,6 +4,19 @@ mod utils;
 
 
 fn main() {
-    play_it();
+    let args: Vec<String> = std::env::args().collect();
+    let result = match args.get(1).map(|a| a.as_str()) {
+        Some("beatmap") => utils::beatmap::run(&args[2..]),
+        Some("fx") => utils::effects::run(&args[2..]),
+        Some("sfx") => utils::synth::run(&args[2..]),
+        Some("visualize") => utils::visualizer::run(&args[2..]),
+        _ => {
+            play_it();
+            return;
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
-
@@ -0,0 +1,441 @@
+use std::sync::Arc;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use crate::utils::audio::WavAudio;
+
+/// A detected note/hit in the track.
+pub(crate) struct Onset {
+    pub time: f64,
+    pub strength: f64, // from 0 to 1
+}
+
+/// Frame layout shared by all the per-frame analysis functions.
+/// `window` and `hop` are in samples.
+pub(crate) struct FrameConfig {
+    pub window: usize,
+    pub hop: usize,
+}
+
+impl FrameConfig {
+    pub(crate) fn hop_secs(&self, audio: &WavAudio) -> f64 {
+        return self.hop as f64 / audio.sample_rate() as f64;
+    }
+
+    /// Number of frames that fit in the track. The last frame may run past
+    /// the end and is padded with zeros.
+    fn frame_count(&self, audio: &WavAudio) -> usize {
+        if self.hop == 0 {
+            return 0;
+        }
+        return audio.samples().len().div_ceil(self.hop);
+    }
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        FrameConfig {
+            window: 1024,
+            hop: 512,
+        }
+    }
+}
+
+fn hann(len: usize) -> Vec<f64> {
+    if len < 2 {
+        return vec![1.0; len];
+    }
+    (0..len)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / (len - 1) as f64).cos())
+        .collect()
+}
+
+/// Spectral flux of the track, one value per hop, normalized so the
+/// largest value is 1.
+/// Only increases in magnitude are counted so decaying notes don't register.
+pub(crate) fn spectral_flux(audio: &WavAudio, config: &FrameConfig) -> Vec<f64> {
+    let samples = audio.samples();
+    let scale = audio.full_scale();
+    let window = hann(config.window);
+    let fft = FftPlanner::<f64>::new().plan_fft_forward(config.window);
+    let bins = config.window / 2;
+
+    let mut previous = vec![0f64; bins];
+    let mut buffer = vec![Complex::new(0f64, 0f64); config.window];
+    let mut flux = Vec::with_capacity(config.frame_count(audio));
+
+    for frame in 0..config.frame_count(audio) {
+        let start = frame * config.hop;
+        for (i, slot) in buffer.iter_mut().enumerate() {
+            let v = samples.get(start + i).map(|s| *s as f64 / scale).unwrap_or(0.0);
+            *slot = Complex::new(v * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+
+        let mut total = 0.0;
+        for (bin, prev) in previous.iter_mut().enumerate() {
+            let mag = buffer[bin].norm();
+            total += (mag - *prev).max(0.0);
+            *prev = mag;
+        }
+        flux.push(total);
+    }
+
+    normalize_max(&mut flux);
+    flux
+}
+
+/// Windowed FFT of the audio leading up to a point in a track, for
+/// following the spectrum while it plays.
+pub(crate) struct SpectrumAnalyzer {
+    fft: Arc<dyn Fft<f64>>,
+    window: Vec<f64>,
+    buffer: Vec<Complex<f64>>,
+    bins: usize,
+}
+
+impl SpectrumAnalyzer {
+    /// `window` is the FFT size in samples. Only bins up to `max_freq` are
+    /// returned.
+    pub(crate) fn new(sample_rate: u32, window: usize, max_freq: f64) -> SpectrumAnalyzer {
+        let window = window.max(2);
+        let bins = ((max_freq / sample_rate as f64 * window as f64) as usize).clamp(1, window / 2);
+        SpectrumAnalyzer {
+            fft: FftPlanner::<f64>::new().plan_fft_forward(window),
+            window: hann(window),
+            buffer: vec![Complex::new(0.0, 0.0); window],
+            bins,
+        }
+    }
+
+    pub(crate) fn bins(&self) -> usize {
+        return self.bins;
+    }
+
+    /// Magnitude of each bin for the window ending at `time`, 1 being a
+    /// full scale sine.
+    pub(crate) fn spectrum(&mut self, audio: &WavAudio, time: f64) -> Vec<f64> {
+        let size = self.window.len();
+        let samples = audio.get_slice_back_seconds(time, size as f64 / audio.sample_rate() as f64);
+        let scale = audio.full_scale();
+        // right aligned, so the start of the track is padded with silence
+        let offset = size - samples.len().min(size);
+        for (i, slot) in self.buffer.iter_mut().enumerate() {
+            let v = if i < offset { 0.0 } else { samples[i - offset] as f64 / scale };
+            *slot = Complex::new(v * self.window[i], 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+        // a Hann window halves the amplitude, and half the energy is in
+        // the negative frequencies
+        let norm = 4.0 / size as f64;
+        return self.buffer[..self.bins].iter().map(|c| c.norm() * norm).collect();
+    }
+}
+
+/// Picks peaks out of an onset envelope (e.g. from [`spectral_flux`]).
+///
+/// A frame counts as an onset if it is the largest value within `spread`
+/// frames on either side and sits at least `threshold` above the local mean.
+/// Onsets closer than `min_gap` seconds to the previous one are dropped.
+pub(crate) fn detect_onsets(
+    envelope: &[f64],
+    hop_secs: f64,
+    spread: usize,
+    threshold: f64,
+    min_gap: f64,
+) -> Vec<Onset> {
+    let mut onsets: Vec<Onset> = Vec::new();
+    for (i, &v) in envelope.iter().enumerate() {
+        let b = i.saturating_sub(spread);
+        let e = (i + spread + 1).min(envelope.len());
+        let neighbourhood = &envelope[b..e];
+        let is_peak = neighbourhood.iter().all(|n| *n <= v);
+        let mean = neighbourhood.iter().sum::<f64>() / neighbourhood.len() as f64;
+        if !is_peak || v < mean + threshold {
+            continue;
+        }
+        let time = i as f64 * hop_secs;
+        if let Some(last) = onsets.last() {
+            if time - last.time < min_gap {
+                continue;
+            }
+        }
+        onsets.push(Onset { time, strength: v });
+    }
+    onsets
+}
+
+/// Guesses the tempo in BPM from the spacing between onsets.
+///
+/// Every pair of onsets less than two seconds apart votes for the tempo its
+/// gap implies, folded into `min_bpm..2 * min_bpm` so that half and double
+/// time agree. Returns None if there are not enough onsets to vote.
+pub(crate) fn estimate_tempo(onsets: &[Onset], min_bpm: f64) -> Option<f64> {
+    let bins = min_bpm.ceil() as usize;
+    let mut votes = vec![0f64; bins + 1];
+    for (i, a) in onsets.iter().enumerate() {
+        for b in onsets[i + 1..].iter() {
+            let gap = b.time - a.time;
+            if gap > 2.0 {
+                break;
+            }
+            if gap <= 0.0 {
+                continue;
+            }
+            let mut bpm = 60.0 / gap;
+            while bpm < min_bpm {
+                bpm *= 2.0;
+            }
+            while bpm >= min_bpm * 2.0 {
+                bpm /= 2.0;
+            }
+            votes[(bpm - min_bpm).round() as usize] += a.strength * b.strength;
+        }
+    }
+
+    let best = votes
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .filter(|(_, v)| **v > 0.0)?
+        .0;
+
+    // refine using the neighbouring bins
+    let mut weighted = 0.0;
+    let mut total = 0.0;
+    for (i, v) in votes.iter().enumerate().take(best + 2).skip(best.saturating_sub(1)) {
+        weighted += (min_bpm + i as f64) * v;
+        total += v;
+    }
+    Some(weighted / total)
+}
+
+/// Estimates the tempo in BPM from the autocorrelation of an onset envelope
+/// (e.g. from [`spectral_flux`]), searching `min_bpm..max_bpm`.
+///
+/// Each lag is also credited with half the correlation at twice that lag,
+/// which favours the actual beat over its subdivisions. Returns None if the
+/// envelope is too short or has no periodicity at all.
+pub(crate) fn tempo_from_envelope(envelope: &[f64], hop_secs: f64, min_bpm: f64, max_bpm: f64) -> Option<f64> {
+    let min_lag = ((60.0 / max_bpm) / hop_secs).floor().max(1.0) as usize;
+    let max_lag = ((60.0 / min_bpm) / hop_secs).ceil() as usize;
+    // the scores look up to twice the longest lag, plus one for the
+    // interpolation, and that lag needs some overlap left to average over
+    if envelope.len() <= (max_lag + 1) * 2 {
+        return None;
+    }
+    let mean = envelope.iter().sum::<f64>() / envelope.len() as f64;
+    let centered: Vec<f64> = envelope.iter().map(|v| v - mean).collect();
+    let autocorrelation = |lag: usize| -> f64 {
+        let sum: f64 = centered.iter().zip(centered[lag..].iter()).map(|(a, b)| a * b).sum();
+        sum / (centered.len() - lag) as f64
+    };
+
+    // one extra lag on each side for the interpolation below
+    let scores: Vec<f64> = (min_lag - 1..=max_lag + 1)
+        .map(|lag| autocorrelation(lag) + 0.5 * autocorrelation(lag * 2))
+        .collect();
+    let (best, best_score) = scores[1..scores.len() - 1]
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, v)| (i + 1, *v))?;
+    if best_score <= 0.0 {
+        return None;
+    }
+
+    // fit a parabola through the peak and its neighbours for a sub-frame lag
+    let (l, c, r) = (scores[best - 1], scores[best], scores[best + 1]);
+    let denom = l - 2.0 * c + r;
+    let offset = if denom.abs() > 1e-12 { 0.5 * (l - r) / denom } else { 0.0 };
+    let mut lag = (min_lag - 1 + best) as f64 + offset.clamp(-0.5, 0.5);
+
+    // a frame is ~10ms, too coarse for a beat grid that has to stay in sync
+    // for minutes, so refine against the peak a few beats further out
+    for beats in [16usize, 8, 4, 2] {
+        let center = (lag * beats as f64).round() as usize;
+        let reach = beats / 2 + 1;
+        if center + reach + 1 >= envelope.len() / 2 {
+            continue;
+        }
+        let Some(low) = center.checked_sub(reach) else {
+            continue;
+        };
+        let peak = (low..=center + reach)
+            .max_by(|a, b| autocorrelation(*a).total_cmp(&autocorrelation(*b)))?;
+        let Some(before) = peak.checked_sub(1) else {
+            continue;
+        };
+        let (l, c, r) = (autocorrelation(before), autocorrelation(peak), autocorrelation(peak + 1));
+        let denom = l - 2.0 * c + r;
+        let offset = if denom.abs() > 1e-12 { 0.5 * (l - r) / denom } else { 0.0 };
+        lag = (peak as f64 + offset.clamp(-0.5, 0.5)) / beats as f64;
+        break;
+    }
+    Some(60.0 / (lag * hop_secs))
+}
+
+/// A detected fundamental frequency.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Pitch {
+    pub frequency: f64,
+    /// How periodic the window was, from 0 (noise) to 1 (pure tone).
+    pub clarity: f64,
+}
+
+impl Pitch {
+    /// Position within the octave from 0 to 1, with 0 at A.
+    pub(crate) fn pitch_class(&self) -> f64 {
+        return (12.0 * (self.frequency / 440.0).log2()).rem_euclid(12.0) / 12.0;
+    }
+}
+
+/// YIN pitch detection over one window of samples. The window should hold
+/// at least two periods of `min_freq`.
+///
+/// `threshold` is how aperiodic a window may be and still count as pitched,
+/// around 0.1 to 0.2 works for most material. Returns None for unpitched or
+/// silent windows.
+pub(crate) fn detect_pitch(
+    samples: &[i32],
+    sample_rate: u32,
+    min_freq: f64,
+    max_freq: f64,
+    threshold: f64,
+) -> Option<Pitch> {
+    let rate = sample_rate as f64;
+    let min_tau = (rate / max_freq).floor().max(2.0) as usize;
+    let max_tau = (rate / min_freq).ceil() as usize;
+    let width = samples.len().checked_sub(max_tau + 1)?;
+    if width < max_tau {
+        return None;
+    }
+
+    // difference function, then normalized by its running mean
+    let x: Vec<f64> = samples.iter().map(|s| *s as f64).collect();
+    let mut cmnd = vec![1.0; max_tau + 2];
+    let mut running = 0.0;
+    for tau in 1..=max_tau + 1 {
+        let diff: f64 = (0..width).map(|j| (x[j] - x[j + tau]).powi(2)).sum();
+        running += diff;
+        if running > 0.0 {
+            cmnd[tau] = diff * tau as f64 / running;
+        }
+    }
+
+    // first dip under the threshold, followed down to its minimum
+    let mut tau = (min_tau..=max_tau).find(|t| cmnd[*t] < threshold)?;
+    while tau < max_tau && cmnd[tau + 1] < cmnd[tau] {
+        tau += 1;
+    }
+
+    let (l, c, r) = (cmnd[tau - 1], cmnd[tau], cmnd[tau + 1]);
+    let denom = l - 2.0 * c + r;
+    let offset = if denom.abs() > 1e-12 { 0.5 * (l - r) / denom } else { 0.0 };
+    Some(Pitch {
+        frequency: rate / (tau as f64 + offset.clamp(-0.5, 0.5)),
+        clarity: (1.0 - c).clamp(0.0, 1.0),
+    })
+}
+
+/// Pitch of the `window` seconds of `audio` leading up to `time`, looking
+/// for anything between 80Hz and 1kHz.
+pub(crate) fn pitch_at(audio: &WavAudio, time: f64, window: f64) -> Option<Pitch> {
+    let samples = audio.get_slice_back_seconds(time, window);
+    return detect_pitch(samples, audio.sample_rate(), 80.0, 1000.0, 0.15);
+}
+
+fn normalize_max(values: &mut [f64]) {
+    let max = values.iter().cloned().fold(0f64, f64::max);
+    if max > 0.0 {
+        for v in values.iter_mut() {
+            *v /= max;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 44100;
+
+    fn sine(freq: f64, len: usize) -> Vec<i32> {
+        (0..len)
+            .map(|i| ((2.0 * std::f64::consts::PI * freq * i as f64 / RATE as f64).sin() * 16000.0) as i32)
+            .collect()
+    }
+
+    /// A short decaying 1kHz blip every beat for `secs` seconds.
+    fn click_track(bpm: f64, secs: f64) -> WavAudio {
+        let period = 60.0 / bpm * RATE as f64;
+        let len = (secs * RATE as f64) as usize;
+        let mut samples = vec![0i32; len];
+        let mut beat = 0.0;
+        while (beat as usize) < len {
+            let start = beat as usize;
+            for (i, s) in samples[start..].iter_mut().take(800).enumerate() {
+                let t = i as f64 / RATE as f64;
+                *s = ((2.0 * std::f64::consts::PI * 1000.0 * t).sin() * (-t * 200.0).exp() * 20000.0) as i32;
+            }
+            beat += period;
+        }
+        WavAudio::from_samples(samples, RATE, 16)
+    }
+
+    #[test]
+    fn pitch_of_a_sine() {
+        let pitch = detect_pitch(&sine(440.0, 4096), RATE, 80.0, 1000.0, 0.15).unwrap();
+        assert!((pitch.frequency - 440.0).abs() < 0.02, "{}", pitch.frequency);
+        assert!(pitch.clarity > 0.95, "{}", pitch.clarity);
+        assert!(pitch.pitch_class().abs() < 1e-3);
+    }
+
+    #[test]
+    fn pitch_at_looks_back_from_the_time() {
+        let audio = WavAudio::from_samples(sine(220.0, RATE as usize), RATE, 16);
+        let pitch = pitch_at(&audio, 0.5, 0.05).unwrap();
+        assert!((pitch.frequency - 220.0).abs() < 0.05, "{}", pitch.frequency);
+    }
+
+    #[test]
+    fn silence_has_no_pitch_or_tempo() {
+        assert!(detect_pitch(&vec![0; 4096], RATE, 80.0, 1000.0, 0.15).is_none());
+        // too short to hold two periods of the lowest frequency
+        assert!(detect_pitch(&sine(440.0, 600), RATE, 80.0, 1000.0, 0.15).is_none());
+
+        let silent = WavAudio::from_samples(vec![0; RATE as usize * 10], RATE, 16);
+        let config = FrameConfig::default();
+        let flux = spectral_flux(&silent, &config);
+        assert_eq!(tempo_from_envelope(&flux, config.hop_secs(&silent), 80.0, 160.0), None);
+        assert!(detect_onsets(&flux, config.hop_secs(&silent), 4, 0.05, 0.08).is_empty());
+        assert_eq!(estimate_tempo(&[], 80.0), None);
+    }
+
+    #[test]
+    fn tempo_of_a_click_track() {
+        let audio = click_track(120.0, 20.0);
+        let config = FrameConfig::default();
+        let hop_secs = config.hop_secs(&audio);
+        let flux = spectral_flux(&audio, &config);
+
+        let bpm = tempo_from_envelope(&flux, hop_secs, 80.0, 160.0).unwrap();
+        assert!((bpm - 120.0).abs() < 0.5, "{}", bpm);
+
+        let onsets = detect_onsets(&flux, hop_secs, 4, 0.05, 0.08);
+        assert_eq!(onsets.len(), 40);
+        let bpm = estimate_tempo(&onsets, 80.0).unwrap();
+        assert!((bpm - 120.0).abs() < 0.5, "{}", bpm);
+    }
+
+    #[test]
+    fn tempo_votes_fold_into_one_octave() {
+        // onsets every 0.25s are 240 BPM, folded down to 120
+        let onsets: Vec<Onset> = (0..16)
+            .map(|i| Onset {
+                time: i as f64 * 0.25,
+                strength: 1.0,
+            })
+            .collect();
+        let bpm = estimate_tempo(&onsets, 80.0).unwrap();
+        assert!((bpm - 120.0).abs() < 1e-9, "{}", bpm);
+    }
+}
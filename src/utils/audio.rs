@@ -1,8 +1,22 @@
 // yeah ignore this
 
+use crate::utils::decode::decode;
+use crate::utils::resample::resample;
+
 pub(crate) struct WavAudio {
     samples: Vec<i32>,
     bitrate: u32,
+    bits_per_sample: u16,
+}
+
+/// Largest magnitude a sample can have at `bits_per_sample`, used to bring
+/// samples into the -1 to 1 range.
+pub(crate) fn full_scale(bits_per_sample: u16) -> f64 {
+    return (1i64 << (bits_per_sample.max(1) - 1)) as f64;
+}
+
+pub(crate) fn index_from_secs(secs: f64, sample_rate: u32) -> i32 {
+    return (secs * (sample_rate as f64)).floor() as i32;
 }
 
 fn i32_to_usize_re_lu(v:i32)->usize{
@@ -14,28 +28,80 @@ fn i32_to_usize_re_lu(v:i32)->usize{
 }
 
 impl WavAudio {
+    /// Loads a WAV file, or OGG/FLAC if the matching cargo feature is on.
+    /// All channels are mixed down to mono.
     pub(crate) fn new(filename: &str) -> Result<WavAudio, Box<dyn std::error::Error>>{
-        let mut reader = hound::WavReader::open(filename).unwrap();
-        let spec = reader.spec();
-        let combined_bitrate = spec.sample_rate as u32;
-        let mut combined_samples: Vec<i32> = Vec::new();
-        // if the polarity is reversed shame on you shame on you
-        let num_channels = spec.channels as usize;
-    
-        for sample_chunk in reader.samples::<i32>().collect::<Result<Vec<_>, _>>()?.chunks(num_channels) {
-            let combined_sample = sample_chunk.iter().sum::<i32>() / num_channels as i32;
-            combined_samples.push(combined_sample);
-        }
-        println!("{}, {}, {}, {}", combined_samples.len(), combined_bitrate, spec.sample_rate, spec.bits_per_sample);
-    
+        let decoded = decode(filename)?;
+
         return Ok(WavAudio {
-            samples:combined_samples,
-            bitrate:combined_bitrate
+            samples: decoded.samples,
+            bitrate: decoded.sample_rate,
+            bits_per_sample: decoded.bits_per_sample,
         })
     }
 
+    /// Wraps already decoded mono samples.
+    pub(crate) fn from_samples(samples: Vec<i32>, sample_rate: u32, bits_per_sample: u16) -> WavAudio {
+        return WavAudio {
+            samples,
+            bitrate: sample_rate,
+            bits_per_sample,
+        };
+    }
+
+    /// Returns a copy of this audio converted to `target_rate`, so that
+    /// assets recorded at different rates line up in time-based analysis.
+    pub(crate) fn resampled(&self, target_rate: u32) -> Result<WavAudio, Box<dyn std::error::Error>> {
+        if target_rate == 0 || self.bitrate == 0 {
+            return Err(format!("can't resample from {}Hz to {}Hz", self.bitrate, target_rate).into());
+        }
+        return Ok(WavAudio {
+            samples: resample(&self.samples, self.bitrate, target_rate, self.full_scale()),
+            bitrate: target_rate,
+            bits_per_sample: self.bits_per_sample,
+        });
+    }
+
+    pub(crate) fn sample_rate(&self) -> u32 {
+        return self.bitrate;
+    }
+
+    pub(crate) fn samples(&self) -> &[i32] {
+        return &self.samples;
+    }
+
+    /// Length of the track in seconds.
+    pub(crate) fn duration_secs(&self) -> f64 {
+        return self.samples.len() as f64 / self.bitrate as f64;
+    }
+
+    /// See [`full_scale`].
+    pub(crate) fn full_scale(&self) -> f64 {
+        return full_scale(self.bits_per_sample);
+    }
+
+    pub(crate) fn bits_per_sample(&self) -> u16 {
+        return self.bits_per_sample;
+    }
+
+    /// Samples scaled to -1 to 1.
+    pub(crate) fn to_f32(&self) -> Vec<f32> {
+        let scale = self.full_scale();
+        return self.samples.iter().map(|s| (*s as f64 / scale) as f32).collect();
+    }
+
+    /// The opposite of `to_f32`, clipping anything outside -1 to 1.
+    pub(crate) fn from_f32(samples: &[f32], sample_rate: u32, bits_per_sample: u16) -> WavAudio {
+        let scale = full_scale(bits_per_sample);
+        let samples = samples
+            .iter()
+            .map(|s| (*s as f64 * scale).round().clamp(-scale, scale - 1.0) as i32)
+            .collect();
+        return WavAudio::from_samples(samples, sample_rate, bits_per_sample);
+    }
+
     pub(crate) fn get_index_from_secs(&self, secs: f64) -> i32 {
-        return (secs * (self.bitrate as f64)).floor() as i32;
+        return index_from_secs(secs, self.bitrate);
     }
     // note: if OOB, to prevent stochastic behavior in the FFT function we are supposed to pad it with zeros
     fn get_slice(&self, b: i32, e: i32) -> &[i32] {
@@ -0,0 +1,234 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufReader;
+use crate::utils::audio::{full_scale, index_from_secs};
+
+/// A WAV file that is decoded a chunk at a time instead of all at once.
+///
+/// Only the most recent `capacity` (mono) samples are kept around, which is
+/// enough for the look-back windows the visualizer asks for. Reading ahead
+/// happens lazily whenever a query asks for audio past what has been decoded.
+/// `MusicTrack` plays WAVs through this so they don't have to be decoded
+/// before the game starts.
+///
+/// There is one read position, so anything reading two far apart places
+/// should open the file twice rather than share one of these.
+pub(crate) struct StreamingWav {
+    reader: hound::WavReader<BufReader<File>>,
+    bitrate: u32,
+    bits_per_sample: u16,
+    channels: usize,
+    total_samples: usize,
+    buffer: VecDeque<i32>,
+    capacity: usize,
+    // index (in mono samples) of buffer[0] within the whole track
+    buffer_start: usize,
+    chunk: usize,
+}
+
+impl StreamingWav {
+    /// Opens `filename` for streaming. `look_back` is the longest window (in
+    /// seconds) that will be queried.
+    pub(crate) fn open(filename: &str, look_back: f64) -> Result<StreamingWav, Box<dyn std::error::Error>> {
+        let reader = hound::WavReader::open(filename)?;
+        let spec = reader.spec();
+        let chunk = 4096;
+        // room for a whole chunk on top of the look-back, so reading ahead
+        // never throws away what was just asked for
+        let capacity = (look_back * spec.sample_rate as f64).ceil() as usize + chunk;
+        return Ok(StreamingWav {
+            total_samples: reader.duration() as usize,
+            reader,
+            bitrate: spec.sample_rate,
+            bits_per_sample: spec.bits_per_sample,
+            channels: spec.channels as usize,
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
+            buffer_start: 0,
+            chunk,
+        });
+    }
+
+    /// Length of the track in (mono) samples.
+    pub(crate) fn len(&self) -> usize {
+        return self.total_samples;
+    }
+
+    pub(crate) fn bits_per_sample(&self) -> u16 {
+        return self.bits_per_sample;
+    }
+
+    pub(crate) fn full_scale(&self) -> f64 {
+        return full_scale(self.bits_per_sample);
+    }
+
+    pub(crate) fn get_index_from_secs(&self, secs: f64) -> i32 {
+        return index_from_secs(secs, self.bitrate);
+    }
+
+    fn buffer_end(&self) -> usize {
+        return self.buffer_start + self.buffer.len();
+    }
+
+    /// Decodes up to `max` more samples (and never more than `chunk`) into the
+    /// ring buffer, dropping the oldest ones once it is full. Returns false at
+    /// the end of the file.
+    fn decode_chunk(&mut self, max: usize) -> Result<bool, hound::Error> {
+        let max = max.min(self.chunk);
+        let mut decoded = 0;
+        let mut frame: Vec<i32> = Vec::with_capacity(self.channels);
+        let mut samples = self.reader.samples::<i32>();
+        while decoded < max {
+            frame.clear();
+            for _ in 0..self.channels {
+                match samples.next() {
+                    Some(s) => frame.push(s?),
+                    None => break,
+                }
+            }
+            if frame.len() < self.channels {
+                break;
+            }
+            let combined_sample = frame.iter().sum::<i32>() / self.channels as i32;
+            if self.buffer.len() == self.capacity {
+                self.buffer.pop_front();
+                self.buffer_start += 1;
+            }
+            self.buffer.push_back(combined_sample);
+            decoded += 1;
+        }
+        return Ok(decoded > 0);
+    }
+
+    /// Makes sure everything before `index` has been decoded. Seeks back and
+    /// starts over if `index` is older than what the ring buffer still holds.
+    fn fill_to(&mut self, index: usize, keep: usize) -> Result<(), hound::Error> {
+        let index = index.min(self.total_samples);
+        let wanted_start = index.saturating_sub(keep);
+        // seek if the window is behind the buffer, or far enough ahead that
+        // everything decoded on the way would be thrown away anyway
+        if wanted_start < self.buffer_start || wanted_start > self.buffer_end() {
+            self.reader.seek(wanted_start as u32)?;
+            self.buffer.clear();
+            self.buffer_start = wanted_start;
+        }
+        while self.buffer_end() < index {
+            if !self.decode_chunk(index - self.buffer_end())? {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    /// The sample at `index`, None past the end. Meant for playing straight
+    /// through, so it decodes a chunk ahead whenever it runs out.
+    pub(crate) fn sample(&mut self, index: usize) -> Result<Option<i32>, hound::Error> {
+        if index >= self.total_samples {
+            return Ok(None);
+        }
+        if index < self.buffer_start || index >= self.buffer_end() {
+            self.fill_to(index + self.chunk, self.chunk)?;
+        }
+        return Ok(index
+            .checked_sub(self.buffer_start)
+            .and_then(|i| self.buffer.get(i))
+            .copied());
+    }
+
+    /// Equivalent of `WavAudio::get_slice_back_seconds`: the `look_back`
+    /// seconds of audio ending at `point`.
+    ///
+    /// The slice may be shorter than asked for near the start or end of the
+    /// track. A `look_back` longer than what `open` was told to keep grows
+    /// the ring buffer to fit.
+    pub(crate) fn get_slice_back_seconds(&mut self, point: f64, look_back: f64) -> Result<&[i32], hound::Error> {
+        let e = self.get_index_from_secs(point).max(0) as usize;
+        let b = e.saturating_sub(self.get_index_from_secs(look_back).max(0) as usize);
+        let wanted = (e - b).min(self.total_samples) + self.chunk;
+        if wanted > self.capacity {
+            self.capacity = wanted;
+            self.buffer.reserve(wanted - self.buffer.len());
+        }
+        self.fill_to(e, e - b)?;
+
+        let start = self.buffer_start;
+        let end = self.buffer_end();
+        let contiguous = self.buffer.make_contiguous();
+        let b2 = b.clamp(start, end) - start;
+        let e2 = e.clamp(start, end) - start;
+        return Ok(&contiguous[b2..e2]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::audio::WavAudio;
+
+    /// Writes a 16 bit WAV of `frames` frames to the temp dir, each channel
+    /// a different ramp so mixing them down is checked too.
+    fn write_wav(name: &str, channels: u16, frames: usize) -> String {
+        let path = std::env::temp_dir().join(format!("luigi_flowey_{}_{}.wav", name, std::process::id()));
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..frames {
+            for c in 0..channels as usize {
+                let v = ((i * 37 + c * 1000) % 20000) as i32 - 10000;
+                writer.write_sample(v as i16).unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn check_sequential(name: &str, channels: u16) {
+        let path = write_wav(name, channels, 20000);
+        let decoded = WavAudio::new(&path).unwrap();
+        let mut stream = StreamingWav::open(&path, 0.0).unwrap();
+        assert_eq!(stream.len(), decoded.samples().len());
+        for (i, expected) in decoded.samples().iter().enumerate() {
+            assert_eq!(stream.sample(i).unwrap(), Some(*expected), "sample {}", i);
+        }
+        assert_eq!(stream.sample(decoded.samples().len()).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sequential_mono_matches_decoded() {
+        check_sequential("mono", 1);
+    }
+
+    #[test]
+    fn sequential_stereo_matches_decoded() {
+        check_sequential("stereo", 2);
+    }
+
+    #[test]
+    fn slices_match_decoded() {
+        let path = write_wav("slices", 2, 40000);
+        let decoded = WavAudio::new(&path).unwrap();
+        // keeps 0.1s, less than some of the windows asked for below
+        let mut stream = StreamingWav::open(&path, 0.1).unwrap();
+        // forwards, backwards, past both ends and longer than the ring
+        for (point, look_back) in [
+            (0.5, 0.1),
+            (0.6, 0.05),
+            (0.2, 0.1),
+            (4.0, 0.1),
+            (6.0, 0.5),
+            (0.05, 0.1),
+            (3.0, 2.0),
+            (1.0, 0.1),
+        ] {
+            let expected = decoded.get_slice_back_seconds(point, look_back);
+            let got = stream.get_slice_back_seconds(point, look_back).unwrap();
+            assert_eq!(got, expected, "{}s back from {}s", look_back, point);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
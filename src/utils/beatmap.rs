@@ -0,0 +1,297 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use crate::utils::analysis::{detect_onsets, estimate_tempo, spectral_flux, tempo_from_envelope, FrameConfig};
+use crate::utils::audio::WavAudio;
+use crate::utils::loudness::{detect_silence, envelope, lufs, FollowerConfig, Silence, SHORT_TERM};
+
+/// Quieter than this counts as a gap in the song.
+const SILENCE_DB: f64 = -50.0;
+/// Gaps shorter than this are just rests, not breaks to build a level around.
+const MIN_SILENCE: f64 = 0.5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum EventKind {
+    Onset,
+    Beat,
+}
+
+impl EventKind {
+    fn name(&self) -> &'static str {
+        match self {
+            EventKind::Onset => "onset",
+            EventKind::Beat => "beat",
+        }
+    }
+}
+
+pub(crate) struct BeatEvent {
+    pub time: f64,
+    pub kind: EventKind,
+    pub strength: f64, // from 0 to 1
+    pub loudness: f64, // RMS at this time, from 0 to 1
+}
+
+/// Everything a level designer needs to sync a level to a song.
+pub(crate) struct Beatmap {
+    pub source: String,
+    pub duration: f64,
+    pub bpm: Option<f64>,
+    pub events: Vec<BeatEvent>,
+    /// Seconds between consecutive values of `loudness`.
+    pub loudness_hop: f64,
+    pub loudness: Vec<f64>,
+    /// Short-term loudness of the loudest part of the song, in LUFS, if the
+    /// song is long enough to measure.
+    pub peak_lufs: Option<f64>,
+    pub silences: Vec<Silence>,
+}
+
+impl Beatmap {
+    /// Runs the full analysis over a loaded track.
+    pub(crate) fn analyze(source: &str, audio: &WavAudio) -> Beatmap {
+        let config = FrameConfig::default();
+        let hop_secs = config.hop_secs(audio);
+        let flux = spectral_flux(audio, &config);
+        let duration = audio.duration_secs();
+        let loudness = envelope(
+            audio,
+            0.0..duration,
+            &FollowerConfig {
+                step: hop_secs,
+                ..FollowerConfig::default()
+            },
+        );
+        let onsets = detect_onsets(&flux, hop_secs, 4, 0.05, 0.08);
+        // the autocorrelation copes better with busy songs, the onset
+        // voting with sparse ones where the envelope is mostly empty
+        let bpm = tempo_from_envelope(&flux, hop_secs, 80.0, 160.0).or_else(|| estimate_tempo(&onsets, 80.0));
+
+        let loudness_at = |time: f64| loudness.value_at(time);
+
+        let mut events: Vec<BeatEvent> = onsets
+            .iter()
+            .map(|o| BeatEvent {
+                time: o.time,
+                kind: EventKind::Onset,
+                strength: o.strength,
+                loudness: loudness_at(o.time),
+            })
+            .collect();
+
+        // lay a beat grid over the track, phased on the strongest onset
+        if let (Some(bpm), Some(anchor)) = (
+            bpm,
+            onsets.iter().max_by(|a, b| a.strength.total_cmp(&b.strength)),
+        ) {
+            let period = 60.0 / bpm;
+            let mut time = anchor.time % period;
+            while time < duration {
+                events.push(BeatEvent {
+                    time,
+                    kind: EventKind::Beat,
+                    strength: 1.0,
+                    loudness: loudness_at(time),
+                });
+                time += period;
+            }
+        }
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        return Beatmap {
+            source: source.to_string(),
+            duration,
+            bpm,
+            events,
+            loudness_hop: loudness.step,
+            peak_lufs: lufs(audio, 0.0..duration, SHORT_TERM, 0.5).max(),
+            silences: detect_silence(audio, 0.0..duration, SILENCE_DB, MIN_SILENCE),
+            loudness: loudness.values,
+        };
+    }
+
+    pub(crate) fn write_csv<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        writeln!(out, "time,kind,strength,loudness")?;
+        for e in self.events.iter() {
+            writeln!(out, "{:.4},{},{:.4},{:.4}", e.time, e.kind.name(), e.strength, e.loudness)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write_json<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"source\": \"{}\",", escape_json(&self.source))?;
+        writeln!(out, "  \"duration\": {:.4},", self.duration)?;
+        match self.bpm {
+            Some(bpm) => writeln!(out, "  \"bpm\": {:.2},", bpm)?,
+            None => writeln!(out, "  \"bpm\": null,")?,
+        }
+        writeln!(out, "  \"events\": [")?;
+        for (i, e) in self.events.iter().enumerate() {
+            let sep = if i + 1 < self.events.len() { "," } else { "" };
+            writeln!(
+                out,
+                "    {{\"time\": {:.4}, \"kind\": \"{}\", \"strength\": {:.4}, \"loudness\": {:.4}}}{}",
+                e.time,
+                e.kind.name(),
+                e.strength,
+                e.loudness,
+                sep
+            )?;
+        }
+        writeln!(out, "  ],")?;
+        match self.peak_lufs {
+            Some(peak) => writeln!(out, "  \"peak_lufs\": {:.2},", peak)?,
+            None => writeln!(out, "  \"peak_lufs\": null,")?,
+        }
+        let silences: Vec<String> = self
+            .silences
+            .iter()
+            .map(|s| format!("{{\"start\": {:.4}, \"end\": {:.4}}}", s.start, s.end))
+            .collect();
+        writeln!(out, "  \"silences\": [{}],", silences.join(", "))?;
+        writeln!(out, "  \"loudness_hop\": {:.6},", self.loudness_hop)?;
+        let values: Vec<String> = self.loudness.iter().map(|v| format!("{:.4}", v)).collect();
+        writeln!(out, "  \"loudness\": [{}]", values.join(", "))?;
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+}
+
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Entry point for `luigi_flowey beatmap <input.wav> <output.json|output.csv>`.
+/// The output format is picked from the extension.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (input, output) = match args {
+        [input, output] => (input, output),
+        _ => return Err("usage: luigi_flowey beatmap <input.wav> <output.json|output.csv>".into()),
+    };
+
+    let audio = WavAudio::new(input)?;
+    let beatmap = Beatmap::analyze(input, &audio);
+    let mut out = BufWriter::new(File::create(output)?);
+    if output.to_lowercase().ends_with(".csv") {
+        beatmap.write_csv(&mut out)?;
+    } else {
+        beatmap.write_json(&mut out)?;
+    }
+    out.flush()?;
+
+    println!(
+        "{} events, bpm {}, peak {} LUFS, {} silences -> {}",
+        beatmap.events.len(),
+        beatmap.bpm.map(|b| format!("{:.1}", b)).unwrap_or("unknown".to_string()),
+        beatmap.peak_lufs.map(|p| format!("{:.1}", p)).unwrap_or("unknown".to_string()),
+        beatmap.silences.len(),
+        output
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beatmap(source: &str) -> Beatmap {
+        Beatmap {
+            source: source.to_string(),
+            duration: 2.0,
+            bpm: Some(120.0),
+            events: vec![
+                BeatEvent {
+                    time: 0.5,
+                    kind: EventKind::Beat,
+                    strength: 1.0,
+                    loudness: 0.25,
+                },
+                BeatEvent {
+                    time: 0.75,
+                    kind: EventKind::Onset,
+                    strength: 0.5,
+                    loudness: 0.125,
+                },
+            ],
+            loudness_hop: 0.5,
+            loudness: vec![0.0, 0.5, 1.0],
+            peak_lufs: Some(-14.0),
+            silences: vec![Silence { start: 1.5, end: 2.0 }],
+        }
+    }
+
+    fn json(beatmap: &Beatmap) -> String {
+        let mut out = Vec::new();
+        beatmap.write_json(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escapes_quotes_backslashes_and_control_characters() {
+        assert_eq!(escape_json(r#"a "b" \c"#), r#"a \"b\" \\c"#);
+        assert_eq!(escape_json("a\nb\rc\td"), r"a\nb\rc\td");
+        assert_eq!(escape_json("\u{0}\u{1b}\u{1f} "), r"\u0000\u001b\u001f ");
+        assert_eq!(escape_json("süß"), "süß");
+    }
+
+    #[test]
+    fn json_has_every_field() {
+        let text = json(&beatmap("song.wav"));
+        assert!(text.starts_with("{\n") && text.ends_with("}\n"));
+        for line in [
+            "  \"source\": \"song.wav\",",
+            "  \"duration\": 2.0000,",
+            "  \"bpm\": 120.00,",
+            "    {\"time\": 0.5000, \"kind\": \"beat\", \"strength\": 1.0000, \"loudness\": 0.2500},",
+            "    {\"time\": 0.7500, \"kind\": \"onset\", \"strength\": 0.5000, \"loudness\": 0.1250}",
+            "  \"peak_lufs\": -14.00,",
+            "  \"silences\": [{\"start\": 1.5000, \"end\": 2.0000}],",
+            "  \"loudness_hop\": 0.500000,",
+            "  \"loudness\": [0.0000, 0.5000, 1.0000]",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?} in\n{}", line, text);
+        }
+    }
+
+    #[test]
+    fn json_source_with_a_newline_stays_on_one_line() {
+        let text = json(&beatmap("dir\nsong \"1\".wav"));
+        assert!(text.lines().any(|l| l == r#"  "source": "dir\nsong \"1\".wav","#));
+    }
+
+    #[test]
+    fn json_without_tempo_is_null() {
+        let mut map = beatmap("song.wav");
+        map.bpm = None;
+        assert!(json(&map).lines().any(|l| l == "  \"bpm\": null,"));
+    }
+
+    #[test]
+    fn json_without_peak_is_null() {
+        let mut map = beatmap("song.wav");
+        map.peak_lufs = None;
+        assert!(json(&map).lines().any(|l| l == "  \"peak_lufs\": null,"));
+    }
+
+    #[test]
+    fn csv_has_a_row_per_event() {
+        let mut out = Vec::new();
+        beatmap("song.wav").write_csv(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "time,kind,strength,loudness\n0.5000,beat,1.0000,0.2500\n0.7500,onset,0.5000,0.1250\n"
+        );
+    }
+}
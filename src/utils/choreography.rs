@@ -0,0 +1,365 @@
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use crate::utils::tracker_player::RowEvent;
+
+/// Number of columns enemies can be spawned in, spread across the screen.
+pub(crate) const LANES: usize = 12;
+/// MIDI key of lane 1, middle C.
+const FIRST_LANE_KEY: u8 = 60;
+/// Tracker rows to a MIDI beat, when a module drives the choreography.
+const ROWS_PER_BEAT: f64 = 4.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Enemy {
+    Luigi,
+    Yoshi,
+    Mario,
+    Wario,
+}
+
+impl Enemy {
+    fn from_name(name: &str) -> Option<Enemy> {
+        match name.trim().to_lowercase().as_str() {
+            "luigi" => Some(Enemy::Luigi),
+            "yoshi" => Some(Enemy::Yoshi),
+            "mario" => Some(Enemy::Mario),
+            "wario" => Some(Enemy::Wario),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Action {
+    /// Drop an enemy in from the top of `lane` (1 to `LANES`), falling at
+    /// `speed` pixels per frame.
+    Spawn { enemy: Enemy, lane: usize, speed: i32 },
+    /// Something that happens to the whole screen at once. What each
+    /// number means is up to the game.
+    Pattern(u8),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ChoreoEvent {
+    pub time: f64, // seconds on the music clock
+    pub beat: f64, // beats from the start of the MIDI file
+    pub action: Action,
+}
+
+/// A level's attacks in the order they happen, played back against a clock.
+pub(crate) struct Choreography {
+    events: Vec<ChoreoEvent>,
+    next: usize,
+}
+
+/// What a MIDI track's notes turn into, from the track's name.
+enum TrackRole {
+    Spawns(Enemy),
+    Patterns,
+}
+
+impl Choreography {
+    /// Imports a Standard MIDI File.
+    ///
+    /// Tracks are matched by name: a track called `yoshi`, `mario`, `wario`
+    /// or `luigi` spawns that enemy, with C4 as lane 1, C#4 as lane 2 and so
+    /// on, and the note velocity setting the speed. A track called `pattern`
+    /// turns each note into `Action::Pattern(key - 60)`. Other tracks are
+    /// ignored.
+    ///
+    /// Events can be played back in seconds with [`Choreography::due`], or
+    /// against a tracker module's rows with [`Choreography::due_row`], four
+    /// rows to a beat.
+    pub(crate) fn load_midi(filename: &str) -> Result<Choreography, Box<dyn std::error::Error>> {
+        let bytes = std::fs::read(filename)?;
+        let smf = Smf::parse(&bytes)?;
+        return Ok(Self::from_smf(&smf));
+    }
+
+    fn from_smf(smf: &Smf) -> Choreography {
+        let tempo_map = TempoMap::new(smf);
+
+        let mut events = Vec::new();
+        for track in smf.tracks.iter() {
+            let mut role = None;
+            let mut tick = 0u64;
+            for event in track.iter() {
+                tick += event.delta.as_int() as u64;
+                match event.kind {
+                    TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+                        let name = String::from_utf8_lossy(name);
+                        role = match Enemy::from_name(&name) {
+                            Some(enemy) => Some(TrackRole::Spawns(enemy)),
+                            None if name.trim().eq_ignore_ascii_case("pattern") => Some(TrackRole::Patterns),
+                            None => None,
+                        };
+                    }
+                    TrackEventKind::Midi {
+                        message: MidiMessage::NoteOn { key, vel },
+                        ..
+                    } if vel.as_int() > 0 => {
+                        let key = key.as_int();
+                        if key < FIRST_LANE_KEY {
+                            continue;
+                        }
+                        let action = match role {
+                            Some(TrackRole::Spawns(enemy)) => {
+                                let lane = (key - FIRST_LANE_KEY) as usize + 1;
+                                if lane > LANES {
+                                    continue;
+                                }
+                                Action::Spawn {
+                                    enemy,
+                                    lane,
+                                    speed: 1 + vel.as_int() as i32 * 5 / 127,
+                                }
+                            }
+                            Some(TrackRole::Patterns) => Action::Pattern(key - FIRST_LANE_KEY),
+                            None => continue,
+                        };
+                        events.push(ChoreoEvent {
+                            time: tempo_map.seconds(tick),
+                            beat: tempo_map.beats(tick),
+                            action,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Choreography { events, next: 0 }
+    }
+
+    /// Every event scheduled up to `clock` seconds that hasn't been returned
+    /// yet. Call once per frame with the music clock.
+    pub(crate) fn due(&mut self, clock: f64) -> &[ChoreoEvent] {
+        let start = self.next;
+        while self.next < self.events.len() && self.events[self.next].time <= clock {
+            self.next += 1;
+        }
+        &self.events[start..self.next]
+    }
+
+    /// Every event up to and including `row` that hasn't been returned yet.
+    /// Call with each row a tracker module plays instead of using `due`, so
+    /// attacks follow the song's own speed and tempo changes.
+    pub(crate) fn due_row(&mut self, row: &RowEvent) -> &[ChoreoEvent] {
+        let beat = row.index as f64 / ROWS_PER_BEAT;
+        let start = self.next;
+        while self.next < self.events.len() && self.events[self.next].beat <= beat {
+            self.next += 1;
+        }
+        &self.events[start..self.next]
+    }
+
+    /// Starts over from the first event.
+    pub(crate) fn rewind(&mut self) {
+        self.next = 0;
+    }
+}
+
+/// Converts MIDI ticks to seconds, following tempo changes.
+struct TempoMap {
+    ticks_per_beat: Option<f64>,
+    ticks_per_second: f64,
+    // (tick, seconds at that tick, microseconds per beat from there on)
+    changes: Vec<(u64, f64, f64)>,
+}
+
+impl TempoMap {
+    fn new(smf: &Smf) -> TempoMap {
+        let (ticks_per_beat, ticks_per_second) = match smf.header.timing {
+            Timing::Metrical(tpb) => (Some(tpb.as_int().max(1) as f64), 0.0),
+            Timing::Timecode(fps, subframes) => (None, fps.as_f32() as f64 * subframes as f64),
+        };
+
+        // tempo events can be on any track, usually the first
+        let mut tempos: Vec<(u64, f64)> = Vec::new();
+        for track in smf.tracks.iter() {
+            let mut tick = 0u64;
+            for event in track.iter() {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Meta(MetaMessage::Tempo(us)) = event.kind {
+                    tempos.push((tick, us.as_int() as f64));
+                }
+            }
+        }
+        tempos.sort_by_key(|t| t.0);
+
+        // 120 BPM until told otherwise
+        let mut changes = vec![(0, 0.0, 500_000.0)];
+        for (tick, us) in tempos {
+            let (last_tick, last_secs, last_us) = changes[changes.len() - 1];
+            let secs = last_secs + Self::span(ticks_per_beat, tick - last_tick, last_us);
+            changes.push((tick, secs, us));
+        }
+        TempoMap {
+            ticks_per_beat,
+            ticks_per_second,
+            changes,
+        }
+    }
+
+    fn span(ticks_per_beat: Option<f64>, ticks: u64, us_per_beat: f64) -> f64 {
+        match ticks_per_beat {
+            Some(tpb) => ticks as f64 / tpb * us_per_beat / 1_000_000.0,
+            None => 0.0,
+        }
+    }
+
+    /// Beats since the start. Timecode files have no beats, so those
+    /// count 120 to the minute.
+    fn beats(&self, tick: u64) -> f64 {
+        match self.ticks_per_beat {
+            Some(tpb) => tick as f64 / tpb,
+            None => self.seconds(tick) * 2.0,
+        }
+    }
+
+    fn seconds(&self, tick: u64) -> f64 {
+        if self.ticks_per_beat.is_none() {
+            return tick as f64 / self.ticks_per_second.max(1.0);
+        }
+        let (from_tick, from_secs, us) = self
+            .changes
+            .iter()
+            .rev()
+            .find(|c| c.0 <= tick)
+            .cloned()
+            .unwrap_or(self.changes[0]);
+        from_secs + Self::span(self.ticks_per_beat, tick - from_tick, us)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::{Format, Header, TrackEvent};
+
+    const TICKS_PER_BEAT: u16 = 480;
+
+    fn name(name: &'static str) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())),
+        }
+    }
+
+    fn tempo(delta: u32, bpm: u32) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo((60_000_000 / bpm).into())),
+        }
+    }
+
+    fn note(delta: u32, key: u8, vel: u8) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: MidiMessage::NoteOn {
+                    key: key.into(),
+                    vel: vel.into(),
+                },
+            },
+        }
+    }
+
+    /// 120 BPM for the first two beats, then 60 BPM.
+    fn song() -> Smf<'static> {
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(TICKS_PER_BEAT.into())));
+        let beat = TICKS_PER_BEAT as u32;
+        smf.tracks.push(vec![tempo(0, 120), tempo(beat * 2, 60)]);
+        smf.tracks.push(vec![
+            name("Yoshi"),
+            note(0, 60, 127),
+            note(beat, 71, 0), // note-on with no velocity is a note-off
+            note(0, 71, 64),
+            note(beat * 2, 59, 100), // below lane 1
+            note(0, 72, 100),        // past the last lane
+        ]);
+        smf.tracks.push(vec![name("pattern"), note(beat * 3, 63, 1)]);
+        smf.tracks.push(vec![name("drums"), note(0, 60, 100)]);
+        return smf;
+    }
+
+    fn times(events: &[ChoreoEvent]) -> Vec<f64> {
+        return events.iter().map(|e| e.time).collect();
+    }
+
+    #[test]
+    fn tracks_notes_and_velocities_become_actions() {
+        let mut choreo = Choreography::from_smf(&song());
+        let events = choreo.due(f64::MAX).to_vec();
+        let actions: Vec<Action> = events.iter().map(|e| e.action).collect();
+        assert_eq!(
+            actions,
+            vec![
+                Action::Spawn {
+                    enemy: Enemy::Yoshi,
+                    lane: 1,
+                    speed: 6
+                },
+                Action::Spawn {
+                    enemy: Enemy::Yoshi,
+                    lane: 12,
+                    speed: 3
+                },
+                Action::Pattern(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn tempo_changes_part_way_through() {
+        let mut choreo = Choreography::from_smf(&song());
+        // beat 1 at 120 BPM, then two beats at 120 and one at 60
+        assert_eq!(times(choreo.due(f64::MAX)), vec![0.0, 0.5, 2.0]);
+    }
+
+    #[test]
+    fn due_returns_each_event_once_until_rewound() {
+        let mut choreo = Choreography::from_smf(&song());
+        assert_eq!(times(choreo.due(0.0)), vec![0.0]);
+        assert!(choreo.due(0.0).is_empty());
+        assert!(choreo.due(0.4).is_empty());
+        assert_eq!(times(choreo.due(1.0)), vec![0.5]);
+        assert_eq!(times(choreo.due(10.0)), vec![2.0]);
+        assert!(choreo.due(20.0).is_empty());
+        choreo.rewind();
+        assert_eq!(choreo.due(10.0).len(), 3);
+    }
+
+    #[test]
+    fn due_row_counts_four_rows_to_a_beat() {
+        let mut choreo = Choreography::from_smf(&song());
+        let mut row = RowEvent {
+            time: 0.0,
+            index: 0,
+            order: 0,
+            pattern: 0,
+            row: 0,
+        };
+        let mut beats = Vec::new();
+        for index in 0..16 {
+            row.index = index;
+            for event in choreo.due_row(&row) {
+                beats.push((index, event.beat));
+            }
+        }
+        // the tempo change doesn't matter, only the rows do
+        assert_eq!(beats, vec![(0, 0.0), (4, 1.0), (12, 3.0)]);
+        choreo.rewind();
+        row.index = 100;
+        assert_eq!(choreo.due_row(&row).len(), 3);
+    }
+
+    #[test]
+    fn loads_a_written_file() {
+        let path = std::env::temp_dir().join("choreography_test.mid");
+        song().save(&path).unwrap();
+        let mut choreo = Choreography::load_midi(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(times(choreo.due(f64::MAX)), vec![0.0, 0.5, 2.0]);
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum AudioFormat {
+    Wav,
+    Ogg,
+    Flac,
+}
+
+/// Mono samples straight out of a decoder, before they become a `WavAudio`.
+pub(crate) struct Decoded {
+    pub samples: Vec<i32>,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+}
+
+/// Works out what kind of file `filename` is from its first bytes, falling
+/// back to the extension if the header isn't recognised.
+pub(crate) fn detect_format(filename: &str) -> Result<AudioFormat, Box<dyn std::error::Error>> {
+    let mut magic = [0u8; 4];
+    let read = File::open(filename)?.read(&mut magic)?;
+    match &magic[..read] {
+        b"RIFF" => return Ok(AudioFormat::Wav),
+        b"OggS" => return Ok(AudioFormat::Ogg),
+        b"fLaC" => return Ok(AudioFormat::Flac),
+        _ => {}
+    }
+
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match extension.as_deref() {
+        Some("wav") | Some("wave") => Ok(AudioFormat::Wav),
+        Some("ogg") | Some("oga") => Ok(AudioFormat::Ogg),
+        Some("flac") => Ok(AudioFormat::Flac),
+        _ => Err(format!("don't know how to decode {}", filename).into()),
+    }
+}
+
+/// Decodes any supported file into mono samples.
+pub(crate) fn decode(filename: &str) -> Result<Decoded, Box<dyn std::error::Error>> {
+    match detect_format(filename)? {
+        AudioFormat::Wav => decode_wav(filename),
+        AudioFormat::Ogg => decode_ogg(filename),
+        AudioFormat::Flac => decode_flac(filename),
+    }
+}
+
+/// Averages interleaved channels into one.
+fn mix_down(interleaved: &[i32], num_channels: usize) -> Vec<i32> {
+    // if the polarity is reversed shame on you shame on you
+    interleaved
+        .chunks(num_channels.max(1))
+        .map(|chunk| chunk.iter().sum::<i32>() / chunk.len() as i32)
+        .collect()
+}
+
+fn decode_wav(filename: &str) -> Result<Decoded, Box<dyn std::error::Error>> {
+    let mut reader = hound::WavReader::open(filename)?;
+    let spec = reader.spec();
+    let interleaved = reader.samples::<i32>().collect::<Result<Vec<_>, _>>()?;
+    return Ok(Decoded {
+        samples: mix_down(&interleaved, spec.channels as usize),
+        sample_rate: spec.sample_rate,
+        bits_per_sample: spec.bits_per_sample,
+    });
+}
+
+#[cfg(feature = "ogg")]
+fn decode_ogg(filename: &str) -> Result<Decoded, Box<dyn std::error::Error>> {
+    let mut reader = lewton::inside_ogg::OggStreamReader::new(File::open(filename)?)?;
+    let num_channels = reader.ident_hdr.audio_channels as usize;
+    let mut interleaved: Vec<i32> = Vec::new();
+    while let Some(packet) = reader.read_dec_packet_itl()? {
+        interleaved.extend(packet.iter().map(|s| *s as i32));
+    }
+    return Ok(Decoded {
+        samples: mix_down(&interleaved, num_channels),
+        sample_rate: reader.ident_hdr.audio_sample_rate,
+        bits_per_sample: 16,
+    });
+}
+
+#[cfg(not(feature = "ogg"))]
+fn decode_ogg(filename: &str) -> Result<Decoded, Box<dyn std::error::Error>> {
+    Err(format!("{} is OGG Vorbis, rebuild with `--features ogg` to load it", filename).into())
+}
+
+#[cfg(feature = "flac")]
+fn decode_flac(filename: &str) -> Result<Decoded, Box<dyn std::error::Error>> {
+    let mut reader = claxon::FlacReader::open(filename)?;
+    let info = reader.streaminfo();
+    let interleaved = reader.samples().collect::<Result<Vec<_>, _>>()?;
+    return Ok(Decoded {
+        samples: mix_down(&interleaved, info.channels as usize),
+        sample_rate: info.sample_rate,
+        bits_per_sample: info.bits_per_sample as u16,
+    });
+}
+
+#[cfg(not(feature = "flac"))]
+fn decode_flac(filename: &str) -> Result<Decoded, Box<dyn std::error::Error>> {
+    Err(format!("{} is FLAC, rebuild with `--features flac` to load it", filename).into())
+}
@@ -0,0 +1,374 @@
+use std::collections::VecDeque;
+use raylib::prelude::*;
+use crate::utils::draw_text_anchor::{draw_text_anchored, TextConfig};
+use crate::utils::sound_bank::SoundBank;
+use crate::utils::text_wrap::{wrap_text, WrapConfig};
+
+const PADDING: f32 = 16.0;
+const BORDER: f32 = 4.0;
+/// Seconds to hold after the end of a sentence.
+const SENTENCE_PAUSE: f32 = 0.3;
+/// Seconds to hold after a comma and friends.
+const CLAUSE_PAUSE: f32 = 0.12;
+
+fn pause_after(ch: char) -> f32 {
+    match ch {
+        '.' | '!' | '?' => SENTENCE_PAUSE,
+        ',' | ';' | ':' => CLAUSE_PAUSE,
+        _ => 0.0,
+    }
+}
+
+/// The typing half of a `DialogueBox`: how much of a page is showing, the
+/// stops on punctuation and which characters blip. Knows nothing about
+/// fonts or sounds.
+#[derive(Clone, Debug)]
+pub(crate) struct Typewriter {
+    pub chars_per_second: f32,
+    /// Only every nth character blips, every single one is a buzz.
+    pub blip_every: usize,
+    shown: usize,
+    progress: f32,
+    pause: f32,
+    blips: usize,
+}
+
+impl Typewriter {
+    pub(crate) fn new(chars_per_second: f32, blip_every: usize) -> Typewriter {
+        Typewriter {
+            chars_per_second,
+            blip_every,
+            shown: 0,
+            progress: 0.0,
+            pause: 0.0,
+            blips: 0,
+        }
+    }
+
+    /// How many characters of the page are showing.
+    pub(crate) fn shown(&self) -> usize {
+        self.shown
+    }
+
+    /// Starts over for a new page.
+    pub(crate) fn reset(&mut self) {
+        self.shown = 0;
+        self.progress = 0.0;
+        self.pause = 0.0;
+        self.blips = 0;
+    }
+
+    /// Shows all of `text` at once.
+    pub(crate) fn finish(&mut self, text: &[char]) {
+        self.shown = text.len();
+    }
+
+    /// Types out `dt` seconds more of `text`, returning how many of the new
+    /// characters should blip.
+    pub(crate) fn update(&mut self, dt: f32, text: &[char]) -> usize {
+        if self.shown >= text.len() {
+            return 0;
+        }
+        if self.pause > 0.0 {
+            self.pause -= dt;
+            return 0;
+        }
+
+        let mut blips = 0;
+        self.progress += dt * self.chars_per_second;
+        while self.progress >= 1.0 && self.shown < text.len() {
+            self.progress -= 1.0;
+            let ch = text[self.shown];
+            self.shown += 1;
+            if !ch.is_whitespace() {
+                if self.blips.is_multiple_of(self.blip_every.max(1)) {
+                    blips += 1;
+                }
+                self.blips += 1;
+            }
+            // "3.5" shouldn't stop halfway through
+            let at_break = text.get(self.shown).is_none_or(|c| c.is_whitespace());
+            if pause_after(ch) > 0.0 && at_break {
+                self.pause = pause_after(ch);
+                self.progress = 0.0;
+                break;
+            }
+        }
+        return blips;
+    }
+}
+
+/// One box full of text. Lines are joined with '\n' so revealing is just
+/// counting characters.
+struct Page<'a> {
+    text: Vec<char>,
+    portrait: Option<&'a Texture2D>,
+    voice: Option<String>,
+}
+
+/// An Undertale style text box. Text is typed out a character at a time with
+/// a blip, stops for a moment on punctuation, and waits for the player
+/// before moving on to the next page.
+pub(crate) struct DialogueBox<'a> {
+    pub bounds: Rectangle,
+    pub font_size: f32,
+    pub typing: Typewriter,
+    pub tint: Color,
+    font: &'a Font,
+    pages: VecDeque<Page<'a>>,
+}
+
+impl<'a> DialogueBox<'a> {
+    pub(crate) fn new(font: &'a Font, bounds: Rectangle, font_size: f32) -> DialogueBox<'a> {
+        DialogueBox {
+            bounds,
+            font_size,
+            typing: Typewriter::new(30.0, 2),
+            tint: Color::WHITE,
+            font,
+            pages: VecDeque::new(),
+        }
+    }
+
+    fn inner(&self) -> Rectangle {
+        let inset = BORDER + PADDING;
+        Rectangle::new(
+            self.bounds.x + inset,
+            self.bounds.y + inset,
+            self.bounds.width - inset * 2.0,
+            self.bounds.height - inset * 2.0,
+        )
+    }
+
+    /// Queues `text`, split over as many pages as it needs. `portrait` is
+    /// drawn to the left of the text and `voice` is the sound played for
+    /// the blips.
+    pub(crate) fn say(&mut self, text: &str, portrait: Option<&'a Texture2D>, voice: Option<&str>) {
+        let inner = self.inner();
+        let text_width = match portrait {
+            Some(_) => inner.width - inner.height - PADDING,
+            None => inner.width,
+        };
+        let wrapped = wrap_text(
+            self.font,
+            text,
+            self.font_size,
+            0.0,
+            &WrapConfig {
+                max_width: text_width,
+                max_lines: None,
+                ellipsis: false,
+            },
+        );
+        let lines_per_page = ((inner.height / self.font_size) as usize).max(1);
+        for lines in wrapped.lines.chunks(lines_per_page) {
+            self.pages.push_back(Page {
+                text: lines.join("\n").chars().collect(),
+                portrait,
+                voice: voice.map(|v| v.to_string()),
+            });
+        }
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        !self.pages.is_empty()
+    }
+
+    /// Drops every page still to come, as if they'd all been read.
+    pub(crate) fn close(&mut self) {
+        self.pages.clear();
+        self.typing.reset();
+    }
+
+    /// Whether the current page has been typed out all the way.
+    pub(crate) fn page_done(&self) -> bool {
+        self.pages.front().is_none_or(|p| self.typing.shown() >= p.text.len())
+    }
+
+    /// Types out more of the current page. `skip` shows the rest of it at
+    /// once, `advance` does the same or moves to the next page when it's
+    /// already all shown.
+    pub(crate) fn update(&mut self, dt: f32, advance: bool, skip: bool, sounds: &mut SoundBank) {
+        let Some(page) = self.pages.front() else {
+            return;
+        };
+        if self.typing.shown() >= page.text.len() {
+            if advance {
+                self.pages.pop_front();
+                self.typing.reset();
+            }
+            return;
+        }
+        if advance || skip {
+            self.typing.finish(&page.text);
+            return;
+        }
+        for _ in 0..self.typing.update(dt, &page.text) {
+            if let Some(voice) = &page.voice {
+                sounds.play(voice);
+            }
+        }
+    }
+
+    /// `time` is in seconds, for blinking the arrow that says there's more.
+    pub(crate) fn draw<T: RaylibDraw>(&self, d: &mut T, time: f32) {
+        let Some(page) = self.pages.front() else {
+            return;
+        };
+        d.draw_rectangle_rec(self.bounds, Color::BLACK);
+        d.draw_rectangle_lines_ex(self.bounds, BORDER, self.tint);
+
+        let inner = self.inner();
+        let mut text_x = inner.x;
+        if let Some(portrait) = page.portrait {
+            // fit into a square as tall as the text area
+            let side = inner.height;
+            let scale = (side / portrait.width() as f32).min(side / portrait.height() as f32);
+            let (w, h) = (portrait.width() as f32 * scale, portrait.height() as f32 * scale);
+            d.draw_texture_pro(
+                portrait,
+                Rectangle::new(0.0, 0.0, portrait.width() as f32, portrait.height() as f32),
+                Rectangle::new(inner.x + (side - w) / 2.0, inner.y + (side - h) / 2.0, w, h),
+                Vector2::zero(),
+                0.0,
+                Color::WHITE,
+            );
+            text_x += side + PADDING;
+        }
+
+        let shown: String = page.text[..self.typing.shown()].iter().collect();
+        draw_text_anchored(
+            d,
+            self.font,
+            shown.split('\n').collect(),
+            Vector2::new(text_x, inner.y),
+            self.font_size,
+            &TextConfig {
+                spacing: 0.0,
+                tint: self.tint,
+                paragraph_align: 0.0,
+                anchor_x: 0.0,
+                anchor_y: 0.0,
+                line_spacing: 1.0,
+            },
+        );
+
+        if self.page_done() && (time * 2.0).fract() < 0.5 {
+            let tip = Vector2::new(inner.x + inner.width - 8.0, inner.y + inner.height);
+            d.draw_triangle(
+                tip,
+                tip + Vector2::new(8.0, -10.0),
+                tip + Vector2::new(-8.0, -10.0),
+                self.tint,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        return text.chars().collect();
+    }
+
+    /// A character a second, so each whole second of `update` is one more.
+    fn typewriter(blip_every: usize) -> Typewriter {
+        return Typewriter::new(1.0, blip_every);
+    }
+
+    #[test]
+    fn types_at_the_given_speed() {
+        let text = chars("ab cd");
+        let mut typing = typewriter(1);
+        assert_eq!(typing.update(1.0, &text), 1);
+        assert_eq!(typing.update(0.5, &text), 0);
+        assert_eq!(typing.shown(), 1);
+        assert_eq!(typing.update(0.5, &text), 1);
+        assert_eq!(typing.shown(), 2);
+        // a long frame catches up, the space doesn't blip
+        assert_eq!(typing.update(10.0, &text), 2);
+        assert_eq!(typing.shown(), 5);
+        assert_eq!(typing.update(1.0, &text), 0);
+
+        typing.reset();
+        assert_eq!(typing.shown(), 0);
+        typing.finish(&text);
+        assert_eq!(typing.shown(), 5);
+    }
+
+    #[test]
+    fn stops_longer_after_sentences_than_clauses() {
+        for (text, pause) in [
+            ("ok. no", SENTENCE_PAUSE),
+            ("ok! no", SENTENCE_PAUSE),
+            ("ok? no", SENTENCE_PAUSE),
+            ("ok, no", CLAUSE_PAUSE),
+            ("ok; no", CLAUSE_PAUSE),
+            ("ok: no", CLAUSE_PAUSE),
+            ("ok- no", 0.0),
+        ] {
+            let text = chars(text);
+            let mut typing = typewriter(1);
+            typing.update(10.0, &text);
+            if pause > 0.0 {
+                assert_eq!(typing.shown(), 3, "{:?}", text);
+            }
+            assert_eq!(typing.pause, pause, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn waits_out_the_pause_before_typing_on() {
+        let text = chars("Hi. Yes");
+        let mut typing = typewriter(1);
+        typing.update(10.0, &text);
+        assert_eq!(typing.shown(), 3);
+        typing.update(0.2, &text);
+        assert_eq!(typing.shown(), 3);
+        typing.update(0.2, &text);
+        assert_eq!(typing.shown(), 3);
+        typing.update(1.0, &text);
+        assert_eq!(typing.shown(), 4);
+    }
+
+    #[test]
+    fn only_pauses_before_whitespace_or_the_end() {
+        let mut typing = typewriter(1);
+        typing.update(10.0, &chars("3.5 ok"));
+        assert_eq!(typing.shown(), 6);
+        assert_eq!(typing.pause, 0.0);
+
+        let mut typing = typewriter(1);
+        typing.update(10.0, &chars("wait... what"));
+        assert_eq!(typing.shown(), 7);
+        assert_eq!(typing.pause, SENTENCE_PAUSE);
+
+        let mut typing = typewriter(1);
+        typing.update(10.0, &chars("end."));
+        assert_eq!(typing.shown(), 4);
+        assert_eq!(typing.pause, SENTENCE_PAUSE);
+    }
+
+    #[test]
+    fn blips_every_nth_visible_character() {
+        for (blip_every, expected) in [
+            (1, [1, 0, 1, 0, 1, 0, 1]),
+            (2, [1, 0, 0, 0, 1, 0, 0]),
+            // 0 is taken as every character
+            (0, [1, 0, 1, 0, 1, 0, 1]),
+        ] {
+            let text = chars("a b c d");
+            let mut typing = typewriter(blip_every);
+            let blips: Vec<usize> = (0..text.len()).map(|_| typing.update(1.0, &text)).collect();
+            assert_eq!(blips, expected.to_vec(), "every {}", blip_every);
+        }
+
+        // counting starts over on each page
+        let mut typing = typewriter(3);
+        assert_eq!(typing.update(10.0, &chars("abcd")), 2);
+        typing.reset();
+        assert_eq!(typing.update(10.0, &chars("ab")), 1);
+    }
+}
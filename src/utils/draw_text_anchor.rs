@@ -30,50 +30,67 @@ pub struct TextConfig {
 }
 
 
-/// Draws text that supports paragraph alignment and anchoring.
-/// 
-/// For all align and anchor arguments:
-/// 0 if left aligned, 0.5 for center, 1 if right aligned, transposing
-/// if needed
-/// 
-/// * `paragraph_align` - Affects each line with respect to the longest line.
-/// * `anchor_x` - 0 if position is the left anchor, 1 if position is the right
-///                anchor
-/// * `anchor_y` - ditto, top -> bottom
-/// 
-pub fn draw_text_anchored<T: RaylibDraw>(d: &mut T,
-                          custom_font: &Font, 
-                          lines: Vec<&str>, 
+/// Smallest rectangle covering both.
+fn union(a: Rectangle, b: Rectangle) -> Rectangle {
+    let left = a.x.min(b.x);
+    let top = a.y.min(b.y);
+    let right = (a.x + a.width).max(b.x + b.width);
+    let bottom = (a.y + a.height).max(b.y + b.height);
+    Rectangle::new(left, top, right - left, bottom - top)
+}
+
+/// Where `draw_text_anchored` puts text, worked out without drawing it.
+#[derive(Clone, Debug)]
+pub struct TextLayout {
+    /// One per line, in order.
+    pub lines: Vec<Rectangle>,
+    /// Everything drawn, all lines together.
+    pub bounds: Rectangle,
+}
+
+/// Lays out `lines` the way `draw_text_anchored` would draw them, see there
+/// for what the config does.
+pub fn layout_text(custom_font: &Font,
+                          lines: &[&str],
                           position: impl Into<Vector2>,
                           font_size: f32,
                           config: &TextConfig
-) {
-    let (spacing, tint, paragraph_align, anchor_x, anchor_y,
-        line_spacing) = (config.spacing, config.tint, config.paragraph_align, config.anchor_x, config.anchor_y,
-        config.line_spacing);
+) -> TextLayout {
+    let sizes: Vec<Vector2> = lines.iter().map(|line| {
+        custom_font.measure_text(line, font_size, config.spacing)
+    }).collect();
+    let lengths: Vec<usize> = lines.iter().map(|line| { line.len() }).collect();
+    layout_measured(&sizes, &lengths, position, config)
+}
+
+/// The font free part of `layout_text`: lays out lines already measured as
+/// `sizes`. `lengths` are the lines' lengths in bytes, the widest line gets
+/// `spacing` extra per byte.
+pub fn layout_measured(sizes: &[Vector2],
+                          lengths: &[usize],
+                          position: impl Into<Vector2>,
+                          config: &TextConfig
+) -> TextLayout {
     let pos = position.into();
-    let mut boxes: Vec<Vector2> = Vec::new();
+    let (spacing, paragraph_align, anchor_x, anchor_y,
+        line_spacing) = (config.spacing, config.paragraph_align, config.anchor_x, config.anchor_y,
+        config.line_spacing);
 
-    for line in &lines {
-        let measured = custom_font.measure_text(line, font_size, spacing);
-        boxes.push(measured);
-    }
-    let bounding_box_height: f32 = boxes.iter().map(|item| { item.y }).sum::<f32>() + spacing * ((lines.len() - 1) as f32);
+    let bounding_box_height: f32 = sizes.iter().map(|item| { item.y }).sum::<f32>() + spacing * (sizes.len().saturating_sub(1) as f32);
 
-    let was_iter: Vec<f32> = boxes.iter().map(|item| { item.x }).collect();
+    let was_iter: Vec<f32> = sizes.iter().map(|item| { item.x }).collect();
     let argmax_of = argmax_f32(&was_iter);
 
     let bounding_box_width: f32 = match argmax_of {
         None => { 0.0 }
         Some(val) => {
-            was_iter[val] + (lines[val].len() as f32) * spacing
+            was_iter[val] + (lengths.get(val).cloned().unwrap_or(0) as f32) * spacing
         }
     };
 
     // assume anchor is @ top left for now and text is left aligned
-    let positions: Vec<Vector2> = boxes.iter().enumerate().map(|(index, cur_box)| {
+    let positions: Vec<Vector2> = sizes.iter().enumerate().map(|(index, cur_box)| {
         let line_height = cur_box.y + spacing;
-        let cur_box = &(boxes[index]);
         let para_offset: f32 = (bounding_box_width - cur_box.x) * paragraph_align;
         let horiz_box_offset: f32 = bounding_box_width * anchor_x; 
         let vert_box_offset: f32 = bounding_box_height * anchor_y;
@@ -85,8 +102,40 @@ pub fn draw_text_anchored<T: RaylibDraw>(d: &mut T,
             )
     }).collect();
 
-    
-    for (idx, t_pos) in positions.iter().enumerate() {
+    let line_rects: Vec<Rectangle> = positions.iter().zip(sizes).map(|(p, b)| {
+        Rectangle::new(p.x, p.y, b.x, b.y)
+    }).collect();
+    let bounds = match line_rects.first() {
+        None => { Rectangle::new(pos.x, pos.y, 0.0, 0.0) }
+        Some(first) => {
+            line_rects.iter().skip(1).fold(*first, |all, r| { union(all, *r) })
+        }
+    };
+    TextLayout { lines: line_rects, bounds }
+}
+
+
+/// Draws text that supports paragraph alignment and anchoring.
+/// 
+/// For all align and anchor arguments:
+/// 0 if left aligned, 0.5 for center, 1 if right aligned, transposing
+/// if needed
+/// 
+/// * `paragraph_align` - Affects each line with respect to the longest line.
+/// * `anchor_x` - 0 if position is the left anchor, 1 if position is the right
+///                anchor
+/// * `anchor_y` - ditto, top -> bottom
+/// 
+pub fn draw_text_anchored<T: RaylibDraw>(d: &mut T,
+                          custom_font: &Font, 
+                          lines: Vec<&str>, 
+                          position: impl Into<Vector2>,
+                          font_size: f32,
+                          config: &TextConfig
+) {
+    let layout = layout_text(custom_font, &lines, position, font_size, config);
+
+    for (idx, line_rect) in layout.lines.iter().enumerate() {
 
         // d.draw_rectangle(t_pos.x as i32, 
         //     t_pos.y as i32, 
@@ -97,11 +146,71 @@ pub fn draw_text_anchored<T: RaylibDraw>(d: &mut T,
         d.draw_text_ex(
             custom_font,
             lines[idx],
-            t_pos,
+            Vector2::new(line_rect.x, line_rect.y),
             font_size,
-            spacing,
-            tint
+            config.spacing,
+            config.tint
         );
     }
 
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(paragraph_align: f32, anchor_x: f32, anchor_y: f32) -> TextConfig {
+        TextConfig {
+            spacing: 0.0,
+            tint: Color::WHITE,
+            paragraph_align,
+            anchor_x,
+            anchor_y,
+            line_spacing: 1.0,
+        }
+    }
+
+    /// A 100 wide line over a 40 wide one, both 20 tall.
+    fn layout(config: &TextConfig) -> TextLayout {
+        let sizes = [Vector2::new(100.0, 20.0), Vector2::new(40.0, 20.0)];
+        layout_measured(&sizes, &[10, 4], Vector2::new(200.0, 100.0), config)
+    }
+
+    #[test]
+    fn paragraph_align_moves_shorter_lines_along_the_longest() {
+        for (align, x) in [(0.0, 200.0), (0.5, 230.0), (1.0, 260.0)] {
+            let lines = layout(&config(align, 0.0, 0.0)).lines;
+            assert_eq!(lines[0].x, 200.0);
+            assert_eq!(lines[1].x, x);
+        }
+    }
+
+    #[test]
+    fn anchor_x_moves_the_whole_block() {
+        for (anchor, left) in [(0.0, 200.0), (0.5, 150.0), (1.0, 100.0)] {
+            let laid_out = layout(&config(0.0, anchor, 0.0));
+            assert_eq!(laid_out.bounds.x, left);
+            assert_eq!(laid_out.bounds.width, 100.0);
+            assert_eq!(laid_out.lines[1].x, left);
+        }
+    }
+
+    #[test]
+    fn anchor_y_moves_the_whole_block() {
+        for (anchor, top) in [(0.0, 100.0), (0.5, 80.0), (1.0, 60.0)] {
+            let laid_out = layout(&config(0.0, 0.0, anchor));
+            assert_eq!(laid_out.bounds.y, top);
+            assert_eq!(laid_out.bounds.height, 40.0);
+            assert_eq!(laid_out.lines[1].y, top + 20.0);
+        }
+    }
+
+    #[test]
+    fn no_lines_is_an_empty_box_at_the_position() {
+        let laid_out = layout_measured(&[], &[], Vector2::new(5.0, 7.0), &config(0.5, 0.5, 0.5));
+        assert!(laid_out.lines.is_empty());
+        assert_eq!((laid_out.bounds.x, laid_out.bounds.y), (5.0, 7.0));
+        assert_eq!((laid_out.bounds.width, laid_out.bounds.height), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_1_SQRT_2, TAU};
+use std::fmt;
+use std::str::FromStr;
+use crate::utils::audio::WavAudio;
+use crate::utils::synth::export_wav;
+
+/// Longest tail a delay or reverb is allowed to add to a sound, in seconds.
+const MAX_TAIL: f32 = 4.0;
+
+/// One step of an `EffectChain`. Times are in seconds, frequencies in Hz,
+/// `mix` is how much of the effected signal is blended in from 0 to 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Effect {
+    LowPass { cutoff: f32, q: f32 },
+    HighPass { cutoff: f32, q: f32 },
+    Gain { db: f32 },
+    Delay { time: f32, feedback: f32, mix: f32 },
+    /// `room` and `damping` go from 0 to 1.
+    Reverb { room: f32, damping: f32, mix: f32 },
+    /// Changes pitch without changing length.
+    PitchShift { semitones: f32 },
+    /// Changes length without changing pitch, 2 is twice as long.
+    TimeStretch { factor: f32 },
+}
+
+impl Effect {
+    /// How much silence has to be added after the input so the effect can ring out.
+    fn tail_secs(&self) -> f32 {
+        let tail = match *self {
+            Effect::Delay { time, feedback, .. } if feedback > 0.0 && feedback < 1.0 => {
+                // until the echoes are 60dB down
+                time * (0.001f32.ln() / feedback.ln()).max(1.0)
+            }
+            Effect::Delay { time, .. } => time,
+            Effect::Reverb { room, .. } => {
+                let longest_comb = COMB_TUNING[COMB_TUNING.len() - 1] as f32 / 44100.0;
+                longest_comb * 0.001f32.ln() / reverb_feedback(room).ln()
+            }
+            _ => 0.0,
+        };
+        tail.min(MAX_TAIL)
+    }
+
+    fn process(&self, input: &[f32], sample_rate: u32) -> Vec<f32> {
+        match *self {
+            Effect::LowPass { cutoff, q } => Biquad::low_pass(cutoff, q, sample_rate).process(input),
+            Effect::HighPass { cutoff, q } => Biquad::high_pass(cutoff, q, sample_rate).process(input),
+            Effect::Gain { db } => {
+                let gain = 10f32.powf(db / 20.0);
+                input.iter().map(|s| s * gain).collect()
+            }
+            Effect::Delay { time, feedback, mix } => delay(input, time, feedback, mix, sample_rate),
+            Effect::Reverb { room, damping, mix } => reverb(input, room, damping, mix, sample_rate),
+            Effect::PitchShift { semitones } => pitch_shift(input, semitones, sample_rate),
+            Effect::TimeStretch { factor } => time_stretch(input, factor, sample_rate),
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Effect::LowPass { cutoff, q } => write!(f, "lowpass cutoff={} q={}", cutoff, q),
+            Effect::HighPass { cutoff, q } => write!(f, "highpass cutoff={} q={}", cutoff, q),
+            Effect::Gain { db } => write!(f, "gain db={}", db),
+            Effect::Delay { time, feedback, mix } => {
+                write!(f, "delay time={} feedback={} mix={}", time, feedback, mix)
+            }
+            Effect::Reverb { room, damping, mix } => {
+                write!(f, "reverb room={} damping={} mix={}", room, damping, mix)
+            }
+            Effect::PitchShift { semitones } => write!(f, "pitch semitones={}", semitones),
+            Effect::TimeStretch { factor } => write!(f, "stretch factor={}", factor),
+        }
+    }
+}
+
+impl FromStr for Effect {
+    type Err = String;
+
+    /// Parses the `name key=value ...` form `Display` writes. Missing
+    /// parameters get a sensible default.
+    fn from_str(text: &str) -> Result<Effect, String> {
+        let mut words = text.split_whitespace();
+        let name = words.next().ok_or("empty effect")?;
+        let mut params = HashMap::new();
+        for word in words {
+            let Some((key, value)) = word.split_once('=') else {
+                return Err(format!("expected key=value, got {}", word));
+            };
+            let value = value
+                .parse::<f32>()
+                .map_err(|_| format!("bad value for {}: {}", key, value))?;
+            params.insert(key, value);
+        }
+
+        let mut take = |key: &str, default: f32| params.remove(key).unwrap_or(default);
+        let effect = match name {
+            "lowpass" => Effect::LowPass {
+                cutoff: take("cutoff", 1000.0),
+                q: take("q", FRAC_1_SQRT_2),
+            },
+            "highpass" => Effect::HighPass {
+                cutoff: take("cutoff", 200.0),
+                q: take("q", FRAC_1_SQRT_2),
+            },
+            "gain" => Effect::Gain { db: take("db", 0.0) },
+            "delay" => Effect::Delay {
+                time: take("time", 0.15),
+                feedback: take("feedback", 0.3),
+                mix: take("mix", 0.3),
+            },
+            "reverb" => Effect::Reverb {
+                room: take("room", 0.5),
+                damping: take("damping", 0.5),
+                mix: take("mix", 0.3),
+            },
+            "pitch" => Effect::PitchShift {
+                semitones: take("semitones", 0.0),
+            },
+            "stretch" => Effect::TimeStretch {
+                factor: take("factor", 1.0),
+            },
+            _ => return Err(format!("unknown effect {}", name)),
+        };
+        if let Some(key) = params.keys().next() {
+            return Err(format!("{} has no parameter {}", name, key));
+        }
+        Ok(effect)
+    }
+}
+
+/// Effects applied one after the other. Written out as the effects
+/// separated by `;`, e.g. `pitch semitones=-3; lowpass cutoff=1200`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct EffectChain {
+    pub effects: Vec<Effect>,
+}
+
+impl EffectChain {
+    /// Runs every effect over -1 to 1 `samples`. The result can be longer
+    /// than the input when delays or reverbs ring out.
+    pub(crate) fn process(&self, samples: &[f32], sample_rate: u32) -> Vec<f32> {
+        let mut out = samples.to_vec();
+        for effect in self.effects.iter() {
+            let len = out.len();
+            let tail = (effect.tail_secs() * sample_rate as f32) as usize;
+            out.resize(len + tail, 0.0);
+            out = effect.process(&out, sample_rate);
+            if tail > 0 {
+                // drop whatever part of the tail ended up inaudible
+                let keep = out.iter().rposition(|s| s.abs() > 1e-4).map_or(0, |i| i + 1);
+                out.truncate(keep.max(len));
+            }
+        }
+        out
+    }
+
+    /// Returns a processed copy of `audio` at the same rate and bit depth.
+    pub(crate) fn apply(&self, audio: &WavAudio) -> WavAudio {
+        let processed = self.process(&audio.to_f32(), audio.sample_rate());
+        WavAudio::from_f32(&processed, audio.sample_rate(), audio.bits_per_sample())
+    }
+}
+
+impl fmt::Display for EffectChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let effects: Vec<String> = self.effects.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", effects.join("; "))
+    }
+}
+
+impl FromStr for EffectChain {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<EffectChain, String> {
+        let effects = text
+            .split([';', '\n'])
+            .map(|e| e.trim())
+            .filter(|e| !e.is_empty())
+            .map(|e| e.parse())
+            .collect::<Result<Vec<Effect>, String>>()?;
+        Ok(EffectChain { effects })
+    }
+}
+
+/// An existing sound put through an effect chain, as read from a level file.
+#[derive(Clone, Debug)]
+pub(crate) struct SoundVariant {
+    pub source: String,
+    pub chain: EffectChain,
+}
+
+/// Reads `[name]` sections with a `source = <file>` and an
+/// `effects = <chain>` line each.
+pub(crate) fn parse_variants(text: &str) -> Result<HashMap<String, SoundVariant>, String> {
+    let mut variants = HashMap::new();
+    let mut name: Option<String> = None;
+    let mut source = None;
+    let mut chain = EffectChain::default();
+    let mut finish = |name: Option<String>, source: Option<String>, chain: EffectChain| -> Result<(), String> {
+        if let Some(name) = name {
+            let source = source.ok_or_else(|| format!("{} has no source", name))?;
+            variants.insert(name, SoundVariant { source, chain });
+        }
+        Ok(())
+    };
+
+    for (num, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            finish(name.take(), source.take(), std::mem::take(&mut chain))?;
+            name = Some(section.trim().to_string());
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected `key = value`", num + 1));
+        };
+        match key.trim() {
+            "source" => source = Some(value.trim().to_string()),
+            "effects" => chain = value.parse().map_err(|e| format!("line {}: {}", num + 1, e))?,
+            other => return Err(format!("line {}: unknown key {}", num + 1, other)),
+        }
+    }
+    finish(name, source, chain)?;
+    Ok(variants)
+}
+
+pub(crate) fn load_variants(filename: &str) -> Result<HashMap<String, SoundVariant>, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(filename)?;
+    Ok(parse_variants(&text).map_err(|e| format!("{}: {}", filename, e))?)
+}
+
+/// Second order filter, coefficients from the RBJ audio EQ cookbook.
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    fn new(cutoff: f32, q: f32, sample_rate: u32, high_pass: bool) -> Biquad {
+        let cutoff = cutoff.clamp(1.0, sample_rate as f32 * 0.49);
+        let w0 = TAU * cutoff / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * q.max(0.01));
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+        let (b0, b1) = if high_pass {
+            ((1.0 + cos) / 2.0, -(1.0 + cos))
+        } else {
+            ((1.0 - cos) / 2.0, 1.0 - cos)
+        };
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+
+    fn low_pass(cutoff: f32, q: f32, sample_rate: u32) -> Biquad {
+        Biquad::new(cutoff, q, sample_rate, false)
+    }
+
+    fn high_pass(cutoff: f32, q: f32, sample_rate: u32) -> Biquad {
+        Biquad::new(cutoff, q, sample_rate, true)
+    }
+
+    fn process(&self, input: &[f32]) -> Vec<f32> {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        input
+            .iter()
+            .map(|x| {
+                let y = self.b0 * x + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2;
+                x2 = x1;
+                x1 = *x;
+                y2 = y1;
+                y1 = y;
+                y
+            })
+            .collect()
+    }
+}
+
+fn delay(input: &[f32], time: f32, feedback: f32, mix: f32, sample_rate: u32) -> Vec<f32> {
+    let len = ((time * sample_rate as f32) as usize).max(1);
+    let feedback = feedback.clamp(0.0, 0.99);
+    let mut line = vec![0.0; len];
+    let mut idx = 0;
+    input
+        .iter()
+        .map(|x| {
+            let echo = line[idx];
+            line[idx] = x + echo * feedback;
+            idx = (idx + 1) % len;
+            x + echo * mix
+        })
+        .collect()
+}
+
+// Freeverb's delay lengths, in samples at 44.1kHz
+const COMB_TUNING: [usize; 4] = [1116, 1277, 1422, 1617];
+const ALLPASS_TUNING: [usize; 2] = [556, 225];
+
+fn reverb_feedback(room: f32) -> f32 {
+    0.7 + 0.28 * room.clamp(0.0, 1.0)
+}
+
+/// A small Schroeder reverb: parallel damped combs into series allpasses.
+fn reverb(input: &[f32], room: f32, damping: f32, mix: f32, sample_rate: u32) -> Vec<f32> {
+    let scale = sample_rate as f32 / 44100.0;
+    let feedback = reverb_feedback(room);
+    let damping = damping.clamp(0.0, 1.0) * 0.4;
+
+    let mut wet = vec![0.0; input.len()];
+    for tuning in COMB_TUNING {
+        let len = ((tuning as f32 * scale) as usize).max(1);
+        let mut line = vec![0.0; len];
+        let mut idx = 0;
+        let mut filtered = 0.0;
+        for (x, out) in input.iter().zip(wet.iter_mut()) {
+            let delayed = line[idx];
+            filtered = delayed * (1.0 - damping) + filtered * damping;
+            line[idx] = x + filtered * feedback;
+            idx = (idx + 1) % len;
+            *out += delayed / COMB_TUNING.len() as f32;
+        }
+    }
+    for tuning in ALLPASS_TUNING {
+        let len = ((tuning as f32 * scale) as usize).max(1);
+        let mut line = vec![0.0; len];
+        let mut idx = 0;
+        for s in wet.iter_mut() {
+            let delayed = line[idx];
+            line[idx] = *s + delayed * 0.5;
+            idx = (idx + 1) % len;
+            *s = delayed - *s;
+        }
+    }
+
+    let mix = mix.clamp(0.0, 1.0);
+    input.iter().zip(wet.iter()).map(|(x, w)| x * (1.0 - mix) + w * mix).collect()
+}
+
+/// Stretches `input` to `factor` times its length with WSOLA: overlapping
+/// windows are copied from roughly the right place in the input, each
+/// nudged to line up with the waveform of the one before.
+fn time_stretch(input: &[f32], factor: f32, sample_rate: u32) -> Vec<f32> {
+    if input.is_empty() || factor <= 0.0 || (factor - 1.0).abs() < 1e-3 {
+        return input.to_vec();
+    }
+    let frame = (((0.04 * sample_rate as f32) as usize).max(64) / 2) * 2;
+    let hop = frame / 2;
+    let tolerance = (hop / 2) as isize;
+    // periodic Hann windows at 50% overlap add up to exactly 1
+    let window: Vec<f32> = (0..frame)
+        .map(|i| 0.5 - 0.5 * (TAU * i as f32 / frame as f32).cos())
+        .collect();
+    let at = |i: isize| -> f32 {
+        if i < 0 {
+            return 0.0;
+        }
+        *input.get(i as usize).unwrap_or(&0.0)
+    };
+
+    let out_len = (input.len() as f32 * factor).round() as usize;
+    // frames start half a frame early so the first sample gets full weight
+    let mut out = vec![0.0; out_len + frame + hop];
+    let mut prev = -(hop as isize);
+    for k in 0..=(out_len / hop + 1) {
+        let nominal = (k as f32 * hop as f32 / factor) as isize - hop as isize;
+        let pos = if k == 0 {
+            nominal
+        } else {
+            // what would have come next had we kept copying the previous frame
+            let natural = prev + hop as isize;
+            let mut best = nominal;
+            let mut best_score = f32::MIN;
+            for cand in (nominal - tolerance)..=(nominal + tolerance) {
+                let score: f32 = (0..hop as isize).map(|i| at(natural + i) * at(cand + i)).sum();
+                if score > best_score {
+                    best_score = score;
+                    best = cand;
+                }
+            }
+            best
+        };
+        for (i, w) in window.iter().enumerate() {
+            out[k * hop + i] += at(pos + i as isize) * w;
+        }
+        prev = pos;
+    }
+    out.drain(..hop);
+    out.truncate(out_len);
+    out
+}
+
+/// Stretches by the pitch ratio, then reads the result back faster or
+/// slower so the length comes out the same as the input.
+fn pitch_shift(input: &[f32], semitones: f32, sample_rate: u32) -> Vec<f32> {
+    if semitones == 0.0 || input.is_empty() {
+        return input.to_vec();
+    }
+    let ratio = (semitones / 12.0).exp2();
+    let stretched = time_stretch(input, ratio, sample_rate);
+    let at = |i: isize| -> f32 { *stretched.get(i.max(0) as usize).unwrap_or(&0.0) };
+    (0..input.len())
+        .map(|n| {
+            let t = n as f32 * ratio;
+            let i = t.floor() as isize;
+            let f = t - i as f32;
+            // cubic Hermite through the four nearest samples
+            let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+            let a = -0.5 * p0 + 1.5 * p1 - 1.5 * p2 + 0.5 * p3;
+            let b = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+            let c = -0.5 * p0 + 0.5 * p2;
+            ((a * f + b) * f + c) * f + p1
+        })
+        .collect()
+}
+
+/// Entry point for `luigi_flowey fx <input> <output.wav> "<effects>"`.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (input, output, chain) = match args {
+        [input, output, chain] => (input, output, chain),
+        _ => return Err("usage: luigi_flowey fx <input> <output.wav> \"<effect>; <effect>...\"".into()),
+    };
+
+    let chain: EffectChain = chain.parse()?;
+    let audio = WavAudio::new(input)?;
+    let processed = chain.process(&audio.to_f32(), audio.sample_rate());
+    export_wav(output, &processed, audio.sample_rate())?;
+    println!("{} -> {} ({})", input, output, chain);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(len: usize, sample_rate: u32) -> Vec<f32> {
+        (0..len).map(|i| (TAU * 220.0 * i as f32 / sample_rate as f32).sin() * 0.5).collect()
+    }
+
+    #[test]
+    fn chain_round_trips_through_text() {
+        let chain = EffectChain {
+            effects: vec![
+                Effect::LowPass { cutoff: 1200.0, q: 0.5 },
+                Effect::HighPass { cutoff: 90.5, q: 2.0 },
+                Effect::Gain { db: -6.0 },
+                Effect::Delay { time: 0.25, feedback: 0.4, mix: 0.2 },
+                Effect::Reverb { room: 0.6, damping: 0.1, mix: 0.3 },
+                Effect::PitchShift { semitones: -3.0 },
+                Effect::TimeStretch { factor: 1.5 },
+            ],
+        };
+        let text = chain.to_string();
+        assert_eq!(text.parse::<EffectChain>().unwrap(), chain, "{}", text);
+    }
+
+    #[test]
+    fn missing_parameters_get_defaults() {
+        assert_eq!("gain".parse::<Effect>().unwrap(), Effect::Gain { db: 0.0 });
+        assert_eq!(
+            "lowpass q=2".parse::<Effect>().unwrap(),
+            Effect::LowPass { cutoff: 1000.0, q: 2.0 }
+        );
+    }
+
+    #[test]
+    fn chain_splits_on_semicolons_and_newlines() {
+        let chain: EffectChain = "gain db=1;\n gain db=2\ngain db=3 ;".parse().unwrap();
+        assert_eq!(
+            chain.effects,
+            vec![Effect::Gain { db: 1.0 }, Effect::Gain { db: 2.0 }, Effect::Gain { db: 3.0 }]
+        );
+        assert!("".parse::<EffectChain>().unwrap().effects.is_empty());
+    }
+
+    #[test]
+    fn bad_effects_are_rejected() {
+        for (text, error) in [
+            ("lowpass cutof=100", "lowpass has no parameter cutof"),
+            ("gain db=loud", "bad value for db: loud"),
+            ("gain db", "expected key=value, got db"),
+            ("flange rate=1", "unknown effect flange"),
+        ] {
+            assert_eq!(text.parse::<Effect>().unwrap_err(), error);
+        }
+        assert!("gain db=1; echo".parse::<EffectChain>().is_err());
+    }
+
+    #[test]
+    fn time_stretch_changes_the_length() {
+        let input = sine(10000, 44100);
+        for factor in [0.5, 0.75, 1.5, 2.0] {
+            let out = time_stretch(&input, factor, 44100);
+            assert_eq!(out.len(), (input.len() as f32 * factor).round() as usize, "factor {}", factor);
+        }
+        assert_eq!(time_stretch(&input, 1.0, 44100), input);
+    }
+
+    #[test]
+    fn pitch_shift_keeps_the_length() {
+        let input = sine(10000, 44100);
+        for semitones in [-12.0, -3.0, 5.0, 12.0] {
+            assert_eq!(pitch_shift(&input, semitones, 44100).len(), input.len(), "{} semitones", semitones);
+        }
+    }
+
+    #[test]
+    fn variants_are_read_by_section() {
+        let text = "# comment\n[hurt_low]\nsource = a.wav\neffects = pitch semitones=-3\n\n[plain]\nsource = b.wav\n";
+        let variants = parse_variants(text).unwrap();
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants["hurt_low"].source, "a.wav");
+        assert_eq!(variants["hurt_low"].chain.effects, vec![Effect::PitchShift { semitones: -3.0 }]);
+        assert!(variants["plain"].chain.effects.is_empty());
+    }
+
+    #[test]
+    fn bad_variants_are_rejected() {
+        assert_eq!(parse_variants("[a]\neffects = gain").unwrap_err(), "a has no source");
+        assert_eq!(parse_variants("[a]\nsauce = a.wav").unwrap_err(), "line 2: unknown key sauce");
+        assert_eq!(parse_variants("[a]\nsource a.wav").unwrap_err(), "line 2: expected `key = value`");
+        assert!(parse_variants("[a]\nsource = a.wav\neffects = gain volume=2").is_err());
+    }
+}
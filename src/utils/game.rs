@@ -2,33 +2,66 @@
 use rand::prelude::ThreadRng;
 use rand::seq::SliceRandom;
 use rand::Rng;
+use std::time::{Duration, Instant};
 use raylib::prelude::*;
-use rustfft::{num_complex::Complex, FftPlanner};
+use crate::utils::analysis::{pitch_at, SpectrumAnalyzer};
 use crate::utils::audio::WavAudio;
+use crate::utils::choreography::{Action, ChoreoEvent, Choreography, Enemy, LANES};
+use crate::utils::dialogue::DialogueBox;
+use crate::utils::effects;
 use crate::utils::draw_text_anchor::{draw_text_anchored, TextConfig};
+use crate::utils::music::MusicPlayer;
+use crate::utils::music_layers::{LayerRule, LayeredMusicPlayer, MusicState};
+use crate::utils::perf_overlay::PerfOverlay;
+use crate::utils::rich_text::draw_rich_text_anchored;
+use crate::utils::render_graph::{BarGraph, Fill, Resample, Scale, Smoothing};
+use crate::utils::sound_bank::{SoundBank, SoundCategory, SoundSettings, Variation};
+use crate::utils::synth;
+use crate::utils::tracker_player::TrackerPlayer;
 
 struct GameState<'a> {
     sprites: Vec<MovableSprite<'a>>,
+    tint: Color,
 }
 
 impl<'a> GameState<'a> {
-    /// Updates the position of all sprites stored in this state.
+    /// Updates the position of all sprites stored in this state, and drops
+    /// the ones that have flown off the screen.
     fn update_all(&mut self) {
         for spr in self.sprites.iter_mut() {
             spr.update();
         }
+        self.sprites.retain(|s| s.bounces || s.on_screen());
+    }
+
+    /// Removes the sprites the choreography spawned.
+    fn clear_spawned(&mut self) {
+        self.sprites.retain(|s| s.bounces);
     }
 
     /// Draws all the sprites stored in this state.
     fn draw_all(&self, d: &mut RaylibDrawHandle) {
         for spr in self.sprites.iter() {
-            spr.draw(d);
+            spr.draw(d, self.tint);
         }
     }
 
     fn shuffle(&mut self, rng: &mut ThreadRng) {
         self.sprites.shuffle(rng);
     }
+
+    /// How many sprites can be seen at least partly.
+    fn on_screen(&self) -> usize {
+        self.sprites.iter().filter(|s| s.on_screen()).count()
+    }
+
+    /// Turns every sprite around.
+    fn reverse_all(&mut self) {
+        for spr in self.sprites.iter_mut() {
+            spr.dx = -spr.dx;
+            spr.dy = -spr.dy;
+        }
+    }
 }
 
 struct BBox {
@@ -36,6 +69,86 @@ struct BBox {
     size: (i32, i32),
 }
 
+impl BBox {
+    /// Returns if the point is strictly inside the box.
+    fn contains(&self, x: i32, y: i32) -> bool {
+        (self.anchor.0 < x && x < self.anchor.0 + self.size.0)
+            && (self.anchor.1 < y && y < self.anchor.1 + self.size.1)
+    }
+
+    /// The same box with `margin` added on every side.
+    fn grown(&self, margin: i32) -> BBox {
+        BBox {
+            anchor: (self.anchor.0 - margin, self.anchor.1 - margin),
+            size: (self.size.0 + margin * 2, self.size.1 + margin * 2),
+        }
+    }
+}
+
+impl From<Rectangle> for BBox {
+    fn from(rect: Rectangle) -> BBox {
+        BBox {
+            anchor: (rect.x as i32, rect.y as i32),
+            size: (rect.width as i32, rect.height as i32),
+        }
+    }
+}
+
+/// Bars that rise from the bottom of the screen with the music's spectrum.
+/// The hitboxes come from the same rectangles that are drawn.
+struct SpectrumFloor {
+    graph: BarGraph,
+    analyzer: SpectrumAnalyzer,
+    window_secs: f64,
+}
+
+/// FFT size of the spectrum floor, in samples.
+const FLOOR_WINDOW: usize = 2048;
+
+impl SpectrumFloor {
+    fn new(scr_w: i32, scr_h: i32, sample_rate: u32) -> SpectrumFloor {
+        let mut graph = BarGraph::new(0, scr_w, scr_h, (scr_h as f64 * 0.35) as i32, 16);
+        graph.gap = 6;
+        graph.scale = Scale::Log;
+        graph.resample = Resample::Max;
+        // slow to rise so there's time to get out of the way
+        graph.smoothing = Some(Smoothing { attack: 0.8, decay: 0.9 });
+        graph.fill = Fill::Gradient {
+            base: Color::new(191, 0, 1, 255),
+            tip: Color::new(254, 255, 0, 255),
+        };
+        graph.max_value = Some(0.5);
+        SpectrumFloor {
+            graph,
+            analyzer: SpectrumAnalyzer::new(sample_rate, FLOOR_WINDOW, 4000.0),
+            window_secs: FLOOR_WINDOW as f64 / sample_rate as f64,
+        }
+    }
+
+    /// How much of the music `update` wants to see.
+    fn window_secs(&self) -> f64 {
+        self.window_secs
+    }
+
+    /// Follows the spectrum at the end of `recent`, or sinks back down
+    /// without it.
+    fn update(&mut self, recent: Option<&WavAudio>) {
+        let magnitudes = match recent {
+            Some(audio) => self.analyzer.spectrum(audio, audio.duration_secs()),
+            None => vec![0.0; self.analyzer.bins()],
+        };
+        self.graph.update(&magnitudes);
+    }
+
+    fn hitboxes(&self) -> Vec<BBox> {
+        self.graph.bar_rects().into_iter().map(BBox::from).collect()
+    }
+
+    fn draw(&self, d: &mut RaylibDrawHandle) {
+        self.graph.draw(d);
+    }
+}
+
 struct MovableSprite<'a> {
     texture: &'a Texture2D,
     x: i32,
@@ -44,6 +157,8 @@ struct MovableSprite<'a> {
     dy: i32,
     scr_w: i32,
     scr_h: i32,
+    /// Turns around at the screen edges, otherwise flies off and is removed.
+    bounces: bool,
 }
 
 struct HealthBar {
@@ -195,13 +310,23 @@ impl<'a> MovableSprite<'a> {
             dy,
             scr_w,
             scr_h,
+            bounces: true,
         }
     }
 
     /// Returns if point collides with the sprite anywhere
     fn collides(&self, x: i32, y: i32) -> bool {
-        (self.x < x && x < self.x + self.texture.width())
-            && (self.y < y && y < self.y + self.texture.height())
+        self.get_bb().contains(x, y)
+    }
+
+    /// Returns if point is within `margin` of the sprite without touching it.
+    fn grazes(&self, x: i32, y: i32, margin: i32) -> bool {
+        self.get_bb().grown(margin).contains(x, y) && !self.collides(x, y)
+    }
+
+    fn on_screen(&self) -> bool {
+        let (w, h) = (self.texture.width(), self.texture.height());
+        self.x + w > 0 && self.x < self.scr_w && self.y + h > 0 && self.y < self.scr_h
     }
 
     /// Return the bounding box.
@@ -212,13 +337,18 @@ impl<'a> MovableSprite<'a> {
             size: (self.texture.width(), self.texture.height()),
         }
     }
-    fn draw(&self, d: &mut RaylibDrawHandle) {
-        d.draw_texture(&self.texture, self.x, self.y, Color::WHITE);
+    fn draw(&self, d: &mut RaylibDrawHandle, tint: Color) {
+        d.draw_texture(&self.texture, self.x, self.y, tint);
     }
 
     /// Updates my position.
     /// Called every frame.
     fn update(&mut self) {
+        if !self.bounces {
+            self.x += self.dx;
+            self.y += self.dy;
+            return;
+        }
         let rb_x = self.x + self.texture.width();
         let rb_y = self.y + self.texture.height();
 
@@ -277,10 +407,61 @@ pub fn play_it() {
         .title("FIND LUIGI")
         .build();
 
-    let mut audio = RaylibAudio::init_audio_device().unwrap();
-    let hurt = audio.new_sound("resources/snd_hurt1.wav").unwrap();
-    let dead = audio.new_sound("resources/snd_hurt1_c.wav").unwrap();
-    let heart_break = audio.new_sound("resources/snd_break1.wav").unwrap();
+    let audio = RaylibAudio::init_audio_device().unwrap();
+    let mut sounds = SoundBank::new(&audio, SoundSettings::default());
+    let hit_variation = Variation {
+        pitch: 0.06,
+        volume: 0.1,
+    };
+    sounds
+        .load("hurt", "resources/snd_hurt1.wav", SoundCategory::Sfx, 3, hit_variation)
+        .unwrap();
+    sounds
+        .load("dead", "resources/snd_hurt1_c.wav", SoundCategory::Sfx, 1, Variation::default())
+        .unwrap();
+    sounds
+        .load("heart_break", "resources/snd_break1.wav", SoundCategory::Sfx, 1, Variation::default())
+        .unwrap();
+    let level_sounds = synth::load_sounds("resources/levels/level1.txt").unwrap();
+    for (name, params) in level_sounds.iter() {
+        sounds
+            .load_synth(name, params, SoundCategory::Sfx, 3, Variation::default())
+            .unwrap();
+    }
+    // other takes on the hurt sound so getting hit repeatedly isn't grating
+    let mut hurt_sounds = vec!["hurt".to_string()];
+    let variants = effects::load_variants("resources/levels/level1_variants.txt").unwrap();
+    for (name, variant) in variants.iter() {
+        sounds
+            .load_processed(name, &variant.source, &variant.chain, SoundCategory::Sfx, 3, hit_variation)
+            .unwrap();
+        if variant.source == "resources/snd_hurt1.wav" {
+            hurt_sounds.push(name.clone());
+        }
+    }
+    // music is optional, the game still works without it
+    let mut music = MusicPlayer::new(&audio, 44100);
+    if let Err(e) = music.load("title", "resources/music/title.wav") {
+        eprintln!("no title music: {}", e);
+    }
+    // a tracker module takes over the title screen if there is one
+    let mut title_tracker = TrackerPlayer::new(&audio, 44100);
+    if let Err(e) = title_tracker
+        .load("resources/music/title.xm")
+        .or_else(|_| title_tracker.load("resources/music/title.mod"))
+    {
+        eprintln!("no title module: {}", e);
+    }
+    let music_volume = sounds.settings().category_volume(SoundCategory::Music);
+    music.set_volume(music_volume);
+    title_tracker.set_volume(music_volume);
+    if title_tracker.is_loaded() {
+        title_tracker.play();
+    } else {
+        music.play("title", 0.0);
+    }
+    // bumped on every beat of the title module, then decays
+    let mut title_pulse = 0.0f32;
     let mut luigi_image = Image::load_image("resources/LUIGI_WANTED.png").unwrap();
     let mut wario_image = Image::load_image("resources/WARIO_WANTED.png").unwrap();
     let mut mario_image = Image::load_image("resources/MARIO_WANTED.png").unwrap();
@@ -317,6 +498,17 @@ pub fn play_it() {
         .load_font_ex(&thread, "resources/LINESeedSans_Bd.ttf", 200, None)
         .unwrap();
 
+    // shown over the title screen before the first run, Z and X read through
+    // it and enter skips it
+    let mut intro = DialogueBox::new(
+        &custom_font,
+        Rectangle::new(32.0, scr_h as f32 - 200.0, scr_w as f32 - 64.0, 168.0),
+        32.0,
+    );
+    intro.say("Luigi's gone missing again. Have you seen him anywhere?", Some(&mario_texture), Some("blip"));
+    intro.say("Wah! Whoever finds him first gets the reward... and it's gonna be me!", Some(&wario_texture), Some("blip_low"));
+    intro.say("Yoshi!", Some(&yoshi_texture), Some("blip"));
+
     let text_config = TextConfig {
         spacing: 0.0,
         tint: Color::WHITE,
@@ -327,8 +519,6 @@ pub fn play_it() {
     };
     let mut rng = rand::thread_rng();
 
-    // let audio = WavAudio::new("resources/wav_test_3.wav").unwrap();
-
     let mut total_elapsed_time: f64 = 0.0;
 
     // let pts: Vec<i32> = vec![0, 100, 200, 100, 200, 100, 200, 100, 200, 0];
@@ -361,8 +551,33 @@ pub fn play_it() {
             }
             sprites
         },
+        tint: Color::WHITE,
     };
     game_state.shuffle(&mut rng);
+    // these bounce around the whole time, so the drums only come in once
+    // attacks add to them
+    let crowd = game_state.on_screen();
+    let mut stage_music = LayeredMusicPlayer::new(&audio, 44100);
+    for (filename, rule) in [
+        ("resources/music/stage.wav", LayerRule::Always),
+        ("resources/music/stage_drums.wav", LayerRule::EnemiesAbove { silent: crowd, full: crowd + 20 }),
+        ("resources/music/stage_danger.wav", LayerRule::HealthBelow { silent: 0.6, full: 0.2 }),
+    ] {
+        if let Err(e) = stage_music.add_layer(filename, rule) {
+            eprintln!("no music layer {}: {}", filename, e);
+        }
+    }
+    stage_music.set_volume(music_volume);
+    // a tracker module replaces the stage layers if there is one, and the
+    // choreography then follows its rows instead of the clock
+    let mut stage_tracker = TrackerPlayer::new(&audio, 44100);
+    if let Err(e) = stage_tracker
+        .load("resources/music/stage.xm")
+        .or_else(|_| stage_tracker.load("resources/music/stage.mod"))
+    {
+        eprintln!("no stage module: {}", e);
+    }
+    stage_tracker.set_volume(music_volume);
     let mut crosshair_spr = MultiSprite::new(vec![&crosshair_texture], 0, 0, 0, 1);
     let mut heart_spr = MultiSprite::new(
         vec![&ut_soul_texture, &ut_soul_cracked_texture],
@@ -388,9 +603,6 @@ pub fn play_it() {
         1,
     );
 
-    let audio_duration = 0.30;
-    let expected_length = audio.get_index_from_secs(audio_duration) as usize;
-    let mut planner = FftPlanner::<f64>::new();
     rl.set_target_fps(60);
     let mov_vel = 5;
     let mov_vel_f = 5f32;
@@ -402,16 +614,87 @@ pub fn play_it() {
         hp: 1.0,
     };
     health_bar.set_center(scr_w / 2, (scr_h as f64 * 0.92) as i32);
+    // attacks authored as MIDI, optional like the music
+    let mut choreography = match Choreography::load_midi("resources/levels/level1.mid") {
+        Ok(c) => Some(c),
+        Err(e) => {
+            eprintln!("no level choreography: {}", e);
+            None
+        }
+    };
     let i_frames_per_hit = 60;
     let mut cur_i_frames = 0;
+    // how close counts as a near miss, and how often one makes a sound
+    let graze_margin = 12;
+    let graze_cooldown = 15;
+    let mut cur_graze_cooldown = 0;
+    // pitch detection is too slow to run every frame, and the tint doesn't
+    // need to follow every note anyway
+    let pitch_every = 6;
+    let mut cur_pitch_cooldown = 0;
     let mut dead_for = 0;
     let mut playing = false;
     let mut frames_since_play = 0;
+    let mut spectrum_floor = SpectrumFloor::new(scr_w, scr_h, stage_music.sample_rate());
+    let mut floor_enabled = false;
+    let mut perf = PerfOverlay::new();
+    let mut last_update = Duration::ZERO;
+    let mut last_draw = Duration::ZERO;
     while !rl.window_should_close() {
+        // timings of the frame that just ended, raylib only knows its length now
+        perf.record(rl.get_frame_time(), last_update, last_draw);
+        let update_start = Instant::now();
+        if rl.is_key_pressed(KeyboardKey::KEY_F3) {
+            perf.toggle();
+        }
+        if cur_graze_cooldown > 0 {
+            cur_graze_cooldown -= 1;
+        }
         if (cur_i_frames > 0) {
             cur_i_frames -= 1;
         }
         let enter_pressed = rl.is_key_down(KeyboardKey::KEY_ENTER);
+        if rl.is_key_pressed(KeyboardKey::KEY_M) {
+            sounds.toggle_mute();
+            let music_volume = sounds.settings().category_volume(SoundCategory::Music);
+            music.set_volume(music_volume);
+            stage_music.set_volume(music_volume);
+            title_tracker.set_volume(music_volume);
+            stage_tracker.set_volume(music_volume);
+        }
+        stage_music.set_state(&MusicState {
+            hp: health_bar.hp,
+            enemies: game_state.on_screen(),
+        });
+        music.update();
+        stage_music.update();
+        title_tracker.update();
+        stage_tracker.update();
+        let stage_rows = stage_tracker.due_rows();
+        for row in title_tracker.due_rows() {
+            if row.row % 4 == 0 {
+                title_pulse = 1.0;
+            }
+        }
+        title_pulse *= 0.85;
+        // hazards take on the color of the note the melody is playing
+        if cur_pitch_cooldown > 0 {
+            cur_pitch_cooldown -= 1;
+        } else if let Some(recent) = stage_music.recent(0.05) {
+            cur_pitch_cooldown = pitch_every;
+            if let Some(pitch) = pitch_at(&recent, recent.duration_secs(), 0.05).filter(|p| p.clarity > 0.8) {
+                game_state.tint = Color::color_from_hsv(pitch.pitch_class() as f32 * 360.0, 0.4, 1.0);
+            }
+        }
+        if !playing && rl.is_key_pressed(KeyboardKey::KEY_B) {
+            floor_enabled = !floor_enabled;
+        }
+        if floor_enabled && playing && health_bar.hp > 0.0 {
+            let recent = stage_music.recent(spectrum_floor.window_secs());
+            spectrum_floor.update(recent.as_ref());
+        } else {
+            spectrum_floor.update(None);
+        }
         if playing && health_bar.hp > 0.0 {
             frames_since_play += 1;
         }
@@ -439,39 +722,130 @@ pub fn play_it() {
         total_elapsed_time += rl.get_frame_time() as f64;
         let pressed_state = rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT);
         let mouse_position = rl.get_mouse_position();
+        if !playing {
+            let advance = rl.is_key_pressed(KeyboardKey::KEY_Z);
+            let skip = rl.is_key_pressed(KeyboardKey::KEY_X);
+            intro.update(rl.get_frame_time(), advance, skip, &mut sounds);
+        }
         if rl.is_key_down(KeyboardKey::KEY_ENTER) {
+            // no need to read the intro to start
+            intro.close();
+            if !playing {
+                music.stop(1.0);
+                title_tracker.stop();
+                if stage_tracker.is_loaded() {
+                    stage_tracker.play();
+                } else {
+                    stage_music.play(1.0);
+                }
+            }
             playing = true;
         }
+        if let Some(choreo) = choreography.as_mut() {
+            if playing && health_bar.hp > 0.0 {
+                let due: Vec<ChoreoEvent> = if stage_tracker.is_loaded() {
+                    stage_rows.iter().flat_map(|row| choreo.due_row(row).to_vec()).collect()
+                } else if let Some(clock) = stage_music.clock() {
+                    choreo.due(clock).to_vec()
+                } else {
+                    Vec::new()
+                };
+                for event in due {
+                    match event.action {
+                        Action::Spawn { enemy, lane, speed } => {
+                            let texture = match enemy {
+                                Enemy::Luigi => &luigi_texture,
+                                Enemy::Yoshi => &yoshi_texture,
+                                Enemy::Mario => &mario_texture,
+                                Enemy::Wario => &wario_texture,
+                            };
+                            let lane_w = scr_w / LANES as i32;
+                            let x = lane_w * (lane as i32 - 1) + (lane_w - texture.width()) / 2;
+                            let x = x.clamp(0, scr_w - texture.width());
+                            let mut spr = MovableSprite::new(texture, x, 0, 0, speed, scr_w, scr_h);
+                            spr.bounces = false;
+                            game_state.sprites.push(spr);
+                        }
+                        Action::Pattern(0) => game_state.reverse_all(),
+                        Action::Pattern(_) => {}
+                    }
+                }
+            }
+        }
+        let before_draw = update_start.elapsed();
+        let mut sprite_update = Duration::ZERO;
+        let draw_start = Instant::now();
+        let mut collision_checks = 0;
+        // where the heart blew up this frame, if it did
+        let mut explode_at = None;
         let mut d = rl.begin_drawing(&thread);
         d.clear_background(Color::BLACK);
 
         if health_bar.hp > 0f64 {
             if playing {
+                let sprites_start = Instant::now();
                 game_state.update_all();
+                sprite_update = sprites_start.elapsed();
             }
             if (cur_i_frames == 0 || (cur_i_frames / 6) % 2 == 0) {
                 heart_spr.draw(&mut d);
             }
             if (cur_i_frames == 0) {
                 let (hh, hv) = heart_spr.center();
-                let collides = game_state.sprites.iter().any(|x| x.collides(hh, hv));
+                let collides = game_state.sprites.iter().any(|x| {
+                    collision_checks += 1;
+                    x.collides(hh, hv)
+                }) || (floor_enabled
+                    && spectrum_floor.hitboxes().iter().any(|b| {
+                        collision_checks += 1;
+                        b.contains(hh, hv)
+                    }));
                 if collides && playing {
-                    hurt.play();
                     cur_i_frames = i_frames_per_hit;
                     health_bar.take_damage(0.08);
+                    sounds.play_at("hit", hh as f32, scr_w as f32);
+                    if health_bar.hp > 0.0 {
+                        let hurt = hurt_sounds.choose(&mut rng).unwrap();
+                        sounds.play_at(hurt, hh as f32, scr_w as f32);
+                    } else {
+                        sounds.play_at("dead", hh as f32, scr_w as f32);
+                        explode_at = Some(Vector2::new(hh as f32, hv as f32));
+                    }
+                } else if playing && cur_graze_cooldown == 0 {
+                    let grazed = game_state.sprites.iter().any(|x| {
+                        collision_checks += 1;
+                        x.grazes(hh, hv, graze_margin)
+                    });
+                    if grazed {
+                        cur_graze_cooldown = graze_cooldown;
+                        sounds.play_at("graze", hh as f32, scr_w as f32);
+                    }
                 }
             }
 
+            spectrum_floor.draw(&mut d);
             game_state.draw_all(&mut d);
             health_bar.draw(&mut d);
 
-            if !playing {
+            if !playing && intro.is_open() {
+                let time = d.get_time() as f32;
+                intro.draw(&mut d, time);
+            } else if !playing {
                 draw_text_anchored(
                     &mut d,
                     &custom_font,
                     vec!["Press enter to start"],
                     Vector2::new(scr_w as f32 / 2.0f32, scr_h as f32 / 2.0f32),
-                    100f32,
+                    100f32 * (1.0 + 0.08 * title_pulse),
+                    &text_config,
+                );
+                let floor_state = if floor_enabled { "on" } else { "off" };
+                draw_text_anchored(
+                    &mut d,
+                    &custom_font,
+                    vec![format!("B: spectrum floor {}", floor_state).as_str()],
+                    Vector2::new(scr_w as f32 / 2.0f32, scr_h as f32 / 2.0f32 + 110f32),
+                    30f32,
                     &text_config,
                 );
             }
@@ -481,22 +855,35 @@ pub fn play_it() {
                 heart_spr.x -= 4;
                 heart_spr.y -= 1;
                 heart_spr.set_texture(1);
-                heart_break.play();
+                sounds.play_at("heart_break", heart_spr.center().0 as f32, scr_w as f32);
+                stage_music.stop(0.5);
+                stage_tracker.stop();
             }
             heart_spr.draw(&mut d);
             if dead_for >= 60 {
-                draw_text_anchored(
+                let time = d.get_time() as f32;
+                draw_rich_text_anchored(
                     &mut d,
                     &custom_font,
                     vec![
-                        format!("You survived {}s", frames_since_play / 60).as_str(),
+                        format!("You survived [color=yellow][wave]{}s[/wave][/color]", frames_since_play / 60).as_str(),
                         "Press enter to restart",
                     ],
                     Vector2::new(scr_w as f32 / 2.0f32, scr_h as f32 / 2.0f32),
                     50f32,
                     &text_config,
+                    time,
                 );
                 if enter_pressed {
+                    if stage_tracker.is_loaded() {
+                        stage_tracker.play();
+                    } else {
+                        stage_music.play(0.5);
+                    }
+                    if let Some(choreo) = choreography.as_mut() {
+                        choreo.rewind();
+                    }
+                    game_state.clear_spawned();
                     health_bar.set_hp(1.0);
                     dead_for = 0;
                     heart_spr.set_texture(0);
@@ -504,22 +891,31 @@ pub fn play_it() {
                 }
             }
         }
-        // draw_explosion(&mut cur_explosion, pressed_state, mouse_position, &mut d);
+        draw_explosion(&mut cur_explosion, explode_at, &mut sounds, scr_w, &mut d);
         // draw_cursor(&mut crosshair_spr, mouse_position, &mut d);
+        perf.set_counts(game_state.sprites.len(), collision_checks);
+        perf.draw(&mut d, &custom_font, 8.0, 8.0);
+        // measured before `d` drops, which would include waiting for vsync
+        last_update = before_draw + sprite_update;
+        last_draw = draw_start.elapsed().saturating_sub(sprite_update);
     }
 }
 
+/// Plays the explosion animation, restarting it with a sound panned to
+/// where it is when `trigger` is set.
 fn draw_explosion(
     cur_explosion: &mut MultiSprite,
-    pressed_state: bool,
-    mouse_position: Vector2,
+    trigger: Option<Vector2>,
+    sounds: &mut SoundBank,
+    scr_w: i32,
     mut d: &mut RaylibDrawHandle,
 ) {
     cur_explosion.draw(&mut d);
     cur_explosion.advance();
-    if pressed_state {
-        cur_explosion.set_position_center(mouse_position.x as i32, mouse_position.y as i32);
+    if let Some(at) = trigger {
+        cur_explosion.set_position_center(at.x as i32, at.y as i32);
         cur_explosion.reset();
+        sounds.play_at("explosion", at.x, scr_w as f32);
     }
 }
 
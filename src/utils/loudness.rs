@@ -0,0 +1,269 @@
+use std::f64::consts::PI;
+use std::ops::Range;
+use crate::utils::audio::WavAudio;
+
+/// Window of the EBU R128 short-term loudness, in seconds.
+pub(crate) const SHORT_TERM: f64 = 3.0;
+/// Window of the EBU R128 momentary loudness, in seconds.
+pub(crate) const MOMENTARY: f64 = 0.4;
+
+/// Averaging time of the `Rms` detector, in seconds.
+const RMS_WINDOW: f64 = 0.01;
+
+/// Values measured every `step` seconds, the first one at `start`.
+#[derive(Clone, Debug)]
+pub(crate) struct TimeSeries {
+    pub start: f64,
+    pub step: f64,
+    pub values: Vec<f64>,
+}
+
+impl TimeSeries {
+    pub(crate) fn time_at(&self, i: usize) -> f64 {
+        return self.start + i as f64 * self.step;
+    }
+
+    /// Value at `time` seconds, linearly interpolated. Clamps to the first
+    /// and last values outside the series.
+    pub(crate) fn value_at(&self, time: f64) -> f64 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        let pos = ((time - self.start) / self.step).max(0.0);
+        let i = pos.floor() as usize;
+        if i + 1 >= self.values.len() {
+            return self.values[self.values.len() - 1];
+        }
+        let f = pos - i as f64;
+        return self.values[i] * (1.0 - f) + self.values[i + 1] * f;
+    }
+
+    /// Largest value, or `None` if the series is empty.
+    pub(crate) fn max(&self) -> Option<f64> {
+        return self.values.iter().cloned().reduce(f64::max);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Detector {
+    /// Follows the absolute value, reacts to single loud samples.
+    Peak,
+    /// Follows the mean square, closer to how loud something sounds.
+    Rms,
+}
+
+/// Settings for [`envelope`]. Times are in seconds.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FollowerConfig {
+    pub detector: Detector,
+    /// How quickly the envelope rises when the signal gets louder.
+    pub attack: f64,
+    /// How quickly it falls back when the signal gets quieter.
+    pub release: f64,
+    /// Time between output values.
+    pub step: f64,
+}
+
+impl Default for FollowerConfig {
+    fn default() -> Self {
+        FollowerConfig {
+            detector: Detector::Rms,
+            attack: 0.01,
+            release: 0.2,
+            step: 1.0 / 60.0,
+        }
+    }
+}
+
+/// Linear amplitude to decibels relative to full scale.
+pub(crate) fn to_db(amplitude: f64) -> f64 {
+    return 20.0 * amplitude.max(1e-10).log10();
+}
+
+/// Samples of `audio` between `range.start` and `range.end` seconds, as
+/// indices clamped to the track.
+fn sample_range(audio: &WavAudio, range: &Range<f64>) -> Range<usize> {
+    let rate = audio.sample_rate() as f64;
+    let len = audio.samples().len();
+    let start = ((range.start * rate).max(0.0) as usize).min(len);
+    let end = ((range.end * rate).max(0.0) as usize).clamp(start, len);
+    return start..end;
+}
+
+/// One pole smoothing coefficient that gets 63% of the way in `secs`.
+fn coefficient(secs: f64, sample_rate: f64) -> f64 {
+    if secs <= 0.0 {
+        return 0.0;
+    }
+    return (-1.0 / (secs * sample_rate)).exp();
+}
+
+/// Runs an envelope follower over `range` (in seconds) of `audio`. Values
+/// are linear amplitude from 0 to 1, use [`to_db`] for decibels.
+pub(crate) fn envelope(audio: &WavAudio, range: Range<f64>, config: &FollowerConfig) -> TimeSeries {
+    let rate = audio.sample_rate() as f64;
+    let scale = audio.full_scale();
+    let samples = sample_range(audio, &range);
+    let attack = coefficient(config.attack, rate);
+    let release = coefficient(config.release, rate);
+    let step = ((config.step * rate).round() as usize).max(1);
+
+    // the mean square is averaged evenly first, otherwise a fast attack
+    // would ride the top of every wave and read high
+    let rms_coef = coefficient(RMS_WINDOW, rate);
+    let mut mean_square = 0.0;
+    let mut level = 0.0;
+    let mut values = Vec::with_capacity(samples.len() / step + 1);
+    for (i, s) in audio.samples()[samples.clone()].iter().enumerate() {
+        let v = *s as f64 / scale;
+        let input = match config.detector {
+            Detector::Peak => v.abs(),
+            Detector::Rms => {
+                mean_square = v * v + rms_coef * (mean_square - v * v);
+                mean_square.sqrt()
+            }
+        };
+        let coef = if input > level { attack } else { release };
+        level = input + coef * (level - input);
+        if i % step == 0 {
+            values.push(level);
+        }
+    }
+    return TimeSeries {
+        start: samples.start as f64 / rate,
+        step: step as f64 / rate,
+        values,
+    };
+}
+
+/// Applies a biquad given as `[b0, b1, b2]` and `[a1, a2]` (a0 = 1).
+fn biquad(samples: &[f64], b: [f64; 3], a: [f64; 2]) -> Vec<f64> {
+    let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+    samples
+        .iter()
+        .map(|x| {
+            let y = b[0] * x + b[1] * x1 + b[2] * x2 - a[0] * y1 - a[1] * y2;
+            x2 = x1;
+            x1 = *x;
+            y2 = y1;
+            y1 = y;
+            y
+        })
+        .collect()
+}
+
+/// The BS.1770 K-weighting filter (a high shelf then a high-pass), with
+/// coefficients worked out for any sample rate the way libebur128 does.
+fn k_weight(samples: &[f64], sample_rate: f64) -> Vec<f64> {
+    let k = (PI * 1681.974450955533 / sample_rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = biquad(
+        samples,
+        [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    let k = (PI * 38.13547087602444 / sample_rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    biquad(
+        &shelf,
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    )
+}
+
+/// Loudness in LUFS over a sliding `window` (e.g. [`SHORT_TERM`] or
+/// [`MOMENTARY`]), one value every `step` seconds across `range`.
+///
+/// Each value covers the `window` seconds leading up to its time, so the
+/// first few are measured over less audio. Silence comes out around -200.
+pub(crate) fn lufs(audio: &WavAudio, range: Range<f64>, window: f64, step: f64) -> TimeSeries {
+    let rate = audio.sample_rate() as f64;
+    let scale = audio.full_scale();
+    let samples = sample_range(audio, &range);
+    // start filtering a window early so the first value has history
+    let from = samples.start.saturating_sub((window * rate) as usize);
+    let input: Vec<f64> = audio.samples()[from..samples.end].iter().map(|s| *s as f64 / scale).collect();
+    let weighted = k_weight(&input, rate);
+
+    // running sum of squares so each window is O(1)
+    let mut sums = Vec::with_capacity(weighted.len() + 1);
+    sums.push(0.0);
+    for v in weighted.iter() {
+        sums.push(sums[sums.len() - 1] + v * v);
+    }
+
+    let window_len = ((window * rate) as usize).max(1);
+    let step_len = ((step * rate).round() as usize).max(1);
+    let values = (samples.start..samples.end)
+        .step_by(step_len)
+        .map(|i| {
+            let end = i - from + 1;
+            let begin = end.saturating_sub(window_len);
+            let mean_square = (sums[end] - sums[begin]) / (end - begin) as f64;
+            -0.691 + 10.0 * mean_square.max(1e-20).log10()
+        })
+        .collect();
+    return TimeSeries {
+        start: samples.start as f64 / rate,
+        step: step_len as f64 / rate,
+        values,
+    };
+}
+
+/// A stretch of audio quieter than the threshold, in seconds.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Silence {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Finds every stretch in `range` at least `min_duration` seconds long
+/// where the peak level stays under `threshold_db`.
+pub(crate) fn detect_silence(audio: &WavAudio, range: Range<f64>, threshold_db: f64, min_duration: f64) -> Vec<Silence> {
+    let config = FollowerConfig {
+        detector: Detector::Peak,
+        attack: 0.0,
+        release: 0.01,
+        step: 0.005,
+    };
+    let env = envelope(audio, range.clone(), &config);
+    let end_time = env.time_at(env.values.len());
+
+    let mut silences = Vec::new();
+    let mut quiet_since = None;
+    for (i, v) in env.values.iter().enumerate() {
+        let quiet = to_db(*v) < threshold_db;
+        match (quiet, quiet_since) {
+            (true, None) => quiet_since = Some(env.time_at(i)),
+            (false, Some(start)) => {
+                let end = env.time_at(i);
+                if end - start >= min_duration {
+                    silences.push(Silence { start, end });
+                }
+                quiet_since = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = quiet_since {
+        if end_time - start >= min_duration {
+            silences.push(Silence { start, end: end_time.min(range.end) });
+        }
+    }
+    return silences;
+}
+
+/// Copy of `audio` with the silence under `threshold_db` cut off both ends.
+pub(crate) fn trim_silence(audio: &WavAudio, threshold_db: f64) -> WavAudio {
+    let threshold = 10f64.powf(threshold_db / 20.0) * audio.full_scale();
+    let samples = audio.samples();
+    let loud = |s: &i32| (*s as f64).abs() >= threshold;
+    let start = samples.iter().position(loud).unwrap_or(samples.len());
+    let end = samples.iter().rposition(loud).map_or(start, |i| i + 1);
+    return WavAudio::from_samples(samples[start..end].to_vec(), audio.sample_rate(), audio.bits_per_sample());
+}
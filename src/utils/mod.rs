@@ -1,4 +1,24 @@
 pub(crate) mod draw_text_anchor;
 pub(crate) mod audio;
+pub(crate) mod audio_stream;
+pub(crate) mod choreography;
+pub(crate) mod decode;
+pub(crate) mod dialogue;
+pub(crate) mod resample;
+pub(crate) mod analysis;
+pub(crate) mod loudness;
+pub(crate) mod effects;
+pub(crate) mod music;
+pub(crate) mod music_layers;
+pub(crate) mod perf_overlay;
 pub(crate) mod render_graph;
-pub mod game;
\ No newline at end of file
+pub(crate) mod rich_text;
+pub(crate) mod sound_bank;
+pub(crate) mod text_wrap;
+pub(crate) mod thumbnail;
+pub(crate) mod tracker;
+pub(crate) mod tracker_player;
+pub(crate) mod synth;
+pub(crate) mod beatmap;
+pub(crate) mod visualizer;
+pub mod game;
@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use raylib::prelude::*;
+use crate::utils::audio::WavAudio;
+use crate::utils::audio_stream::StreamingWav;
+use crate::utils::decode::{detect_format, AudioFormat};
+
+/// Frames handed to raylib per stream update. Streams are opened with this as
+/// their sub-buffer size so every update fills a buffer exactly.
+pub(crate) const STREAM_FRAMES: usize = 2048;
+/// Longest window `MusicTrack::recent` can be asked for on a streamed track.
+const LOOK_BACK_SECS: f64 = 0.5;
+
+/// Opens a 16 bit raylib stream whose buffers are `STREAM_FRAMES` long.
+pub(crate) fn open_stream<'aud>(audio: &'aud RaylibAudio, sample_rate: u32, channels: u32) -> AudioStream<'aud> {
+    // raylib-rs has no safe wrapper for this.
+    // SAFETY: it only stores the int in raylib's global audio state, which
+    // `LoadAudioStream` reads on the next line. Streams are only ever opened
+    // from the main thread, so nothing else touches that state meanwhile.
+    unsafe {
+        raylib::ffi::SetAudioStreamBufferSizeDefault(STREAM_FRAMES as i32);
+    }
+    audio.new_audio_stream(sample_rate, 16, channels)
+}
+
+/// Converts a -1 to 1 sample to what raylib expects in a 16 bit stream.
+pub(crate) fn to_i16(v: f32) -> i16 {
+    (v.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+/// The section of a track that repeats forever once playback reaches
+/// `end`. Both are sample indices, `end` is exclusive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct LoopPoints {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Reads the first loop out of a WAV file's `smpl` chunk, if there is one.
+pub(crate) fn read_smpl_loop(filename: &str) -> std::io::Result<Option<LoopPoints>> {
+    let mut file = File::open(filename)?;
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Ok(None);
+    }
+
+    let mut header = [0u8; 8];
+    while file.read_exact(&mut header).is_ok() {
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if &header[0..4] != b"smpl" {
+            // chunks are padded to an even length
+            file.seek(SeekFrom::Current((size + (size & 1)) as i64))?;
+            continue;
+        }
+
+        let mut chunk = vec![0u8; size];
+        file.read_exact(&mut chunk)?;
+        let read_u32 = |at: usize| -> Option<usize> {
+            let b = chunk.get(at..at + 4)?;
+            Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        };
+        // 36 byte header, then 24 bytes per loop: id, type, start, end, fraction, count
+        if read_u32(28).unwrap_or(0) == 0 {
+            return Ok(None);
+        }
+        return match (read_u32(44), read_u32(48)) {
+            (Some(start), Some(end)) if end > start => Ok(Some(LoopPoints { start, end: end + 1 })),
+            _ => Ok(None),
+        };
+    }
+    Ok(None)
+}
+
+/// Reads loop points from `<filename>.loop`, a text file with
+/// `start = <sample>` and `end = <sample>` lines. `end` is exclusive.
+pub(crate) fn read_sidecar_loop(filename: &str) -> Option<LoopPoints> {
+    let text = std::fs::read_to_string(format!("{}.loop", filename)).ok()?;
+    let mut start = None;
+    let mut end = None;
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().parse::<usize>().ok();
+        match key.trim() {
+            "start" => start = value,
+            "end" => end = value,
+            _ => {}
+        }
+    }
+    match (start, end) {
+        (Some(start), Some(end)) if end > start => Some(LoopPoints { start, end }),
+        _ => None,
+    }
+}
+
+/// Where a track's samples come from. WAVs already at the player's rate are
+/// read off disk as they play, anything that has to be decoded or resampled
+/// first is kept in memory.
+enum TrackSource {
+    Decoded(WavAudio),
+    Streamed(StreamingWav),
+}
+
+/// One voice's read position in a track. Each cursor on a streamed track
+/// has its own reader, so voices playing far apart places in the same file
+/// don't keep throwing away each other's decoded audio.
+pub(crate) struct TrackCursor {
+    stream: Option<StreamingWav>,
+}
+
+/// A song, ready to be streamed.
+pub(crate) struct MusicTrack {
+    filename: String,
+    /// Streamed tracks read this for `recent`, playback goes through a
+    /// `TrackCursor` each.
+    source: TrackSource,
+    sample_rate: u32,
+    full_scale: f64,
+    len: usize,
+    loop_points: Option<LoopPoints>,
+}
+
+impl MusicTrack {
+    /// Opens `filename` and converts it to `sample_rate`.
+    ///
+    /// Loop points come from a `.loop` sidecar if present, otherwise from the
+    /// WAV `smpl` chunk. Without either the track plays once.
+    pub(crate) fn load(filename: &str, sample_rate: u32) -> Result<MusicTrack, Box<dyn std::error::Error>> {
+        let mut loop_points = match read_sidecar_loop(filename) {
+            Some(points) => Some(points),
+            None => read_smpl_loop(filename).unwrap_or(None),
+        };
+
+        let streamable = detect_format(filename)? == AudioFormat::Wav
+            && hound::WavReader::open(filename)?.spec().sample_rate == sample_rate;
+        let source = if streamable {
+            TrackSource::Streamed(StreamingWav::open(filename, LOOK_BACK_SECS)?)
+        } else {
+            let source = WavAudio::new(filename)?;
+            if source.sample_rate() == sample_rate {
+                TrackSource::Decoded(source)
+            } else {
+                let ratio = sample_rate as f64 / source.sample_rate() as f64;
+                loop_points = loop_points.map(|p| LoopPoints {
+                    start: (p.start as f64 * ratio).round() as usize,
+                    end: (p.end as f64 * ratio).round() as usize,
+                });
+                TrackSource::Decoded(source.resampled(sample_rate)?)
+            }
+        };
+
+        let (len, full_scale) = match &source {
+            TrackSource::Decoded(audio) => (audio.samples().len(), audio.full_scale()),
+            TrackSource::Streamed(stream) => (stream.len(), stream.full_scale()),
+        };
+        let loop_points = loop_points
+            .map(|p| LoopPoints {
+                start: p.start.min(len),
+                end: p.end.min(len),
+            })
+            .filter(|p| p.end > p.start);
+        Ok(MusicTrack {
+            filename: filename.to_string(),
+            source,
+            sample_rate,
+            full_scale,
+            len,
+            loop_points,
+        })
+    }
+
+    /// The `look_back` seconds of audio ending at `time` as their own
+    /// `WavAudio`, for analysis. Streamed tracks only keep `LOOK_BACK_SECS`.
+    pub(crate) fn recent(&mut self, time: f64, look_back: f64) -> WavAudio {
+        let (samples, bits_per_sample) = match &mut self.source {
+            TrackSource::Decoded(audio) => (audio.get_slice_back_seconds(time, look_back).to_vec(), audio.bits_per_sample()),
+            TrackSource::Streamed(stream) => {
+                let bits_per_sample = stream.bits_per_sample();
+                let samples = stream.get_slice_back_seconds(time, look_back).map(|s| s.to_vec()).unwrap_or_default();
+                (samples, bits_per_sample)
+            }
+        };
+        WavAudio::from_samples(samples, self.sample_rate, bits_per_sample)
+    }
+
+    /// Where in the track we are after `elapsed` samples of playback,
+    /// following the loop. None once a non-looping track has ended.
+    pub(crate) fn position_after(&self, elapsed: usize) -> Option<usize> {
+        match self.loop_points {
+            Some(p) if elapsed >= p.end => Some(p.start + (elapsed - p.start) % (p.end - p.start)),
+            _ if elapsed < self.len => Some(elapsed),
+            _ => None,
+        }
+    }
+
+    /// A new read position for playing this track, see `TrackCursor`.
+    pub(crate) fn cursor(&self) -> Result<TrackCursor, Box<dyn std::error::Error>> {
+        let stream = match &self.source {
+            TrackSource::Decoded(_) => None,
+            TrackSource::Streamed(_) => Some(StreamingWav::open(&self.filename, 0.0)?),
+        };
+        Ok(TrackCursor { stream })
+    }
+
+    /// Sample at index `idx` as -1 to 1, read through `cursor`. Silent past
+    /// the end or if the file can't be read any more.
+    pub(crate) fn sample_at(&self, cursor: &mut TrackCursor, idx: usize) -> f32 {
+        let sample = match (&self.source, cursor.stream.as_mut()) {
+            (TrackSource::Decoded(audio), _) => audio.samples().get(idx).copied(),
+            (TrackSource::Streamed(_), Some(stream)) => stream.sample(idx).unwrap_or(None),
+            (TrackSource::Streamed(_), None) => None,
+        };
+        match sample {
+            Some(s) => (s as f64 / self.full_scale) as f32,
+            None => 0.0,
+        }
+    }
+
+    /// Sample at `elapsed` samples of playback as -1 to 1.
+    fn sample_after(&self, cursor: &mut TrackCursor, elapsed: usize) -> f32 {
+        match self.position_after(elapsed) {
+            Some(i) => self.sample_at(cursor, i),
+            None => 0.0,
+        }
+    }
+}
+
+/// One track being played, possibly fading in or out.
+struct Voice {
+    track: String,
+    cursor: TrackCursor,
+    // samples of this track output so far
+    elapsed: usize,
+    fade: f32,      // 0 is silent, 1 is full volume
+    fade_step: f32, // change in `fade` per sample
+}
+
+impl Voice {
+    fn gain(&self) -> f32 {
+        // equal power, so a crossfade doesn't dip in the middle
+        (self.fade.clamp(0.0, 1.0) * FRAC_PI_2).sin()
+    }
+}
+
+/// Streams background music with loop points and crossfades.
+pub(crate) struct MusicPlayer<'aud> {
+    stream: AudioStream<'aud>,
+    sample_rate: u32,
+    tracks: HashMap<String, MusicTrack>,
+    current: Option<Voice>,
+    fading_out: Vec<Voice>,
+    volume: f32,
+    buffer: Vec<i16>,
+}
+
+impl<'aud> MusicPlayer<'aud> {
+    pub(crate) fn new(audio: &'aud RaylibAudio, sample_rate: u32) -> MusicPlayer<'aud> {
+        let mut stream = open_stream(audio, sample_rate, 1);
+        stream.play();
+        MusicPlayer {
+            stream,
+            sample_rate,
+            tracks: HashMap::new(),
+            current: None,
+            fading_out: Vec::new(),
+            volume: 1.0,
+            buffer: vec![0; STREAM_FRAMES],
+        }
+    }
+
+    pub(crate) fn load(&mut self, name: &str, filename: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let track = MusicTrack::load(filename, self.sample_rate)?;
+        self.tracks.insert(name.to_string(), track);
+        Ok(())
+    }
+
+
+    pub(crate) fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    fn fade_step(&self, secs: f32) -> f32 {
+        if secs <= 0.0 {
+            return f32::INFINITY;
+        }
+        1.0 / (secs * self.sample_rate as f32)
+    }
+
+    /// Switches to `name`, crossfading over `fade_secs`. Does nothing if
+    /// that track is already playing or wasn't loaded.
+    pub(crate) fn play(&mut self, name: &str, fade_secs: f32) {
+        if self.current_track() == Some(name) {
+            return;
+        }
+        let Some(track) = self.tracks.get(name) else {
+            return;
+        };
+        let cursor = match track.cursor() {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                eprintln!("can't play {}: {}", name, e);
+                return;
+            }
+        };
+        self.stop(fade_secs);
+        let step = self.fade_step(fade_secs);
+        self.current = Some(Voice {
+            track: name.to_string(),
+            cursor,
+            elapsed: 0,
+            fade: if step.is_infinite() { 1.0 } else { 0.0 },
+            fade_step: step,
+        });
+    }
+
+    /// Fades the current track out over `fade_secs`.
+    pub(crate) fn stop(&mut self, fade_secs: f32) {
+        let step = self.fade_step(fade_secs);
+        if let Some(mut voice) = self.current.take() {
+            voice.fade_step = -step;
+            self.fading_out.push(voice);
+        }
+    }
+
+    pub(crate) fn current_track(&self) -> Option<&str> {
+        self.current.as_ref().map(|v| v.track.as_str())
+    }
+
+    /// Seconds since the current track started, counting loops, as heard
+    /// through the speakers. This only ever goes up, so it's what gameplay
+    /// should be scheduled against.
+    pub(crate) fn clock(&self) -> Option<f64> {
+        let voice = self.current.as_ref()?;
+        let heard = voice.elapsed.saturating_sub(STREAM_FRAMES);
+        Some(heard as f64 / self.sample_rate as f64)
+    }
+
+    /// Playback position within the current track in seconds, wrapping at the
+    /// loop end.
+    pub(crate) fn position(&self) -> Option<f64> {
+        let voice = self.current.as_ref()?;
+        let track = self.tracks.get(&voice.track)?;
+        let heard = voice.elapsed.saturating_sub(STREAM_FRAMES);
+        let idx = track.position_after(heard)?;
+        Some(idx as f64 / self.sample_rate as f64)
+    }
+
+    /// The last `look_back` seconds heard of the current track, see
+    /// `MusicTrack::recent`.
+    pub(crate) fn recent(&mut self, look_back: f64) -> Option<WavAudio> {
+        let time = self.position()?;
+        let voice = self.current.as_ref()?;
+        let track = self.tracks.get_mut(&voice.track)?;
+        Some(track.recent(time, look_back))
+    }
+
+    /// Feeds raylib more audio. Call once per frame.
+    pub(crate) fn update(&mut self) {
+        while self.stream.is_processed() {
+            self.fill_buffer();
+            self.stream.update(&self.buffer);
+        }
+    }
+
+    fn fill_buffer(&mut self) {
+        // finished fades would only be read to be multiplied by zero
+        self.fading_out.retain(|v| v.fade > 0.0);
+        let tracks = &self.tracks;
+        let volume = self.volume;
+        for (i, out) in self.buffer.iter_mut().enumerate() {
+            let mut mixed = 0.0;
+            for voice in self.current.iter_mut().chain(self.fading_out.iter_mut()) {
+                let gain = voice.gain();
+                if gain > 0.0 {
+                    if let Some(track) = tracks.get(&voice.track) {
+                        mixed += track.sample_after(&mut voice.cursor, voice.elapsed + i) * gain;
+                    }
+                }
+                voice.fade = (voice.fade + voice.fade_step).clamp(0.0, 1.0);
+            }
+            *out = to_i16(mixed * volume);
+        }
+
+        let written = self.buffer.len();
+        for voice in self.current.iter_mut().chain(self.fading_out.iter_mut()) {
+            voice.elapsed += written;
+        }
+        self.fading_out.retain(|v| v.fade > 0.0);
+    }
+}
@@ -0,0 +1,253 @@
+use std::f32::consts::FRAC_PI_2;
+use raylib::prelude::*;
+use crate::utils::audio::WavAudio;
+use crate::utils::music::{open_stream, to_i16, MusicTrack, TrackCursor, STREAM_FRAMES};
+
+/// The parts of the game the soundtrack reacts to.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MusicState {
+    pub hp: f64, // from 0 to 1
+    pub enemies: usize, // on screen
+}
+
+/// How loud a layer should be for a given `MusicState`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum LayerRule {
+    Always,
+    /// Silent at `silent` hp and above, full volume at `full` hp and below.
+    HealthBelow { silent: f64, full: f64 },
+    /// Silent at `silent` enemies or fewer, full volume from `full` enemies.
+    EnemiesAbove { silent: usize, full: usize },
+}
+
+impl LayerRule {
+    pub(crate) fn target_gain(&self, state: &MusicState) -> f32 {
+        // `rising` is whether values above `full` are loud, which can't be
+        // told from `silent` and `full` when they're equal
+        let ramp = |v: f64, silent: f64, full: f64, rising: bool| -> f32 {
+            if silent == full {
+                let loud = if rising { v >= full } else { v <= full };
+                return if loud { 1.0 } else { 0.0 };
+            }
+            ((v - silent) / (full - silent)).clamp(0.0, 1.0) as f32
+        };
+        match *self {
+            LayerRule::Always => 1.0,
+            LayerRule::HealthBelow { silent, full } => ramp(state.hp, silent, full, false),
+            LayerRule::EnemiesAbove { silent, full } => {
+                ramp(state.enemies as f64, silent as f64, full as f64, true)
+            }
+        }
+    }
+}
+
+struct Layer {
+    track: MusicTrack,
+    cursor: TrackCursor,
+    rule: LayerRule,
+    gain: f32,
+    target: f32,
+}
+
+/// Plays several stems of one song in lockstep, fading each in and out
+/// according to its `LayerRule`.
+///
+/// All stems are indexed with the first layer's loop points, so they stay
+/// sample-locked even if their own loop data disagrees.
+pub(crate) struct LayeredMusicPlayer<'aud> {
+    stream: AudioStream<'aud>,
+    sample_rate: u32,
+    layers: Vec<Layer>,
+    elapsed: usize,
+    playing: bool,
+    // master fade used by play/stop, 0 to 1
+    fade: f32,
+    fade_step: f32,
+    /// Seconds a layer takes to go from silent to full volume.
+    pub layer_fade: f32,
+    volume: f32,
+    buffer: Vec<i16>,
+}
+
+impl<'aud> LayeredMusicPlayer<'aud> {
+    pub(crate) fn new(audio: &'aud RaylibAudio, sample_rate: u32) -> LayeredMusicPlayer<'aud> {
+        let mut stream = open_stream(audio, sample_rate, 1);
+        stream.play();
+        LayeredMusicPlayer {
+            stream,
+            sample_rate,
+            layers: Vec::new(),
+            elapsed: 0,
+            playing: false,
+            fade: 0.0,
+            fade_step: 0.0,
+            layer_fade: 2.0,
+            volume: 1.0,
+            buffer: vec![0; STREAM_FRAMES],
+        }
+    }
+
+    /// Adds a stem. The first one added decides the loop points.
+    pub(crate) fn add_layer(&mut self, filename: &str, rule: LayerRule) -> Result<(), Box<dyn std::error::Error>> {
+        let track = MusicTrack::load(filename, self.sample_rate)?;
+        self.layers.push(Layer {
+            cursor: track.cursor()?,
+            track,
+            rule,
+            gain: 0.0,
+            target: 0.0,
+        });
+        Ok(())
+    }
+
+    pub(crate) fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// The rate every layer is resampled to and mixed at.
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Updates every layer's target volume. Layers glide towards it over
+    /// `layer_fade` seconds rather than jumping.
+    pub(crate) fn set_state(&mut self, state: &MusicState) {
+        for layer in self.layers.iter_mut() {
+            layer.target = layer.rule.target_gain(state);
+        }
+    }
+
+    fn fade_step(&self, secs: f32) -> f32 {
+        if secs <= 0.0 {
+            return f32::INFINITY;
+        }
+        1.0 / (secs * self.sample_rate as f32)
+    }
+
+    /// Starts the song from the top, fading in over `fade_secs`. Layers
+    /// start at their target volume so the opening isn't thin.
+    pub(crate) fn play(&mut self, fade_secs: f32) {
+        self.elapsed = 0;
+        self.playing = true;
+        self.fade_step = self.fade_step(fade_secs);
+        self.fade = if self.fade_step.is_infinite() { 1.0 } else { 0.0 };
+        for layer in self.layers.iter_mut() {
+            layer.gain = layer.target;
+        }
+    }
+
+    pub(crate) fn stop(&mut self, fade_secs: f32) {
+        self.fade_step = -self.fade_step(fade_secs);
+    }
+
+    /// Seconds since `play`, counting loops, as heard through the speakers.
+    pub(crate) fn clock(&self) -> Option<f64> {
+        if !self.playing {
+            return None;
+        }
+        let heard = self.elapsed.saturating_sub(STREAM_FRAMES);
+        Some(heard as f64 / self.sample_rate as f64)
+    }
+
+    /// Playback position within the song in seconds, wrapping at the loop
+    /// end.
+    pub(crate) fn position(&self) -> Option<f64> {
+        let layer = self.layers.first()?;
+        if !self.playing {
+            return None;
+        }
+        let heard = self.elapsed.saturating_sub(STREAM_FRAMES);
+        let idx = layer.track.position_after(heard)?;
+        Some(idx as f64 / self.sample_rate as f64)
+    }
+
+    /// The last `look_back` seconds heard of the first layer, see
+    /// `MusicTrack::recent`.
+    pub(crate) fn recent(&mut self, look_back: f64) -> Option<WavAudio> {
+        let time = self.position()?;
+        let layer = self.layers.first_mut()?;
+        Some(layer.track.recent(time, look_back))
+    }
+
+    /// Feeds raylib more audio. Call once per frame.
+    pub(crate) fn update(&mut self) {
+        while self.stream.is_processed() {
+            self.fill_buffer();
+            self.stream.update(&self.buffer);
+        }
+    }
+
+    fn fill_buffer(&mut self) {
+        if !self.playing || self.layers.is_empty() {
+            self.buffer.iter_mut().for_each(|s| *s = 0);
+            return;
+        }
+        let gain_step = 1.0 / (self.layer_fade.max(0.001) * self.sample_rate as f32);
+        for (i, out) in self.buffer.iter_mut().enumerate() {
+            let mixed = match self.layers[0].track.position_after(self.elapsed + i) {
+                Some(idx) => {
+                    let mut sum = 0.0;
+                    for layer in self.layers.iter_mut() {
+                        if layer.gain > 0.0 {
+                            sum += layer.track.sample_at(&mut layer.cursor, idx) * layer.gain;
+                        }
+                        let diff = layer.target - layer.gain;
+                        layer.gain += diff.clamp(-gain_step, gain_step);
+                    }
+                    sum
+                }
+                None => 0.0,
+            };
+            let master = (self.fade.clamp(0.0, 1.0) * FRAC_PI_2).sin();
+            *out = to_i16(mixed * master * self.volume);
+            self.fade = (self.fade + self.fade_step).clamp(0.0, 1.0);
+        }
+        self.elapsed += self.buffer.len();
+        if self.fade_step < 0.0 && self.fade <= 0.0 {
+            self.playing = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gain(rule: LayerRule, hp: f64, enemies: usize) -> f32 {
+        rule.target_gain(&MusicState { hp, enemies })
+    }
+
+    #[test]
+    fn always_is_full() {
+        assert_eq!(gain(LayerRule::Always, 0.0, 0), 1.0);
+        assert_eq!(gain(LayerRule::Always, 1.0, 100), 1.0);
+    }
+
+    #[test]
+    fn health_below_gets_louder_as_health_drops() {
+        let rule = LayerRule::HealthBelow { silent: 0.6, full: 0.2 };
+        for (hp, expected) in [(1.0, 0.0), (0.6, 0.0), (0.4, 0.5), (0.2, 1.0), (0.0, 1.0)] {
+            assert!((gain(rule, hp, 0) - expected).abs() < 1e-6, "hp {}", hp);
+        }
+    }
+
+    #[test]
+    fn enemies_above_gets_louder_with_more_enemies() {
+        let rule = LayerRule::EnemiesAbove { silent: 60, full: 80 };
+        for (enemies, expected) in [(0, 0.0), (60, 0.0), (70, 0.5), (80, 1.0), (200, 1.0)] {
+            assert!((gain(rule, 1.0, enemies) - expected).abs() < 1e-6, "{} enemies", enemies);
+        }
+    }
+
+    #[test]
+    fn equal_thresholds_switch_in_the_right_direction() {
+        let rule = LayerRule::HealthBelow { silent: 0.5, full: 0.5 };
+        assert_eq!(gain(rule, 0.4, 0), 1.0);
+        assert_eq!(gain(rule, 0.5, 0), 1.0);
+        assert_eq!(gain(rule, 0.6, 0), 0.0);
+        let rule = LayerRule::EnemiesAbove { silent: 5, full: 5 };
+        assert_eq!(gain(rule, 1.0, 4), 0.0);
+        assert_eq!(gain(rule, 1.0, 5), 1.0);
+        assert_eq!(gain(rule, 1.0, 6), 1.0);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use raylib::prelude::*;
+use crate::utils::draw_text_anchor::{draw_text_anchored, layout_text, TextConfig};
+use crate::utils::render_graph::{BarGraph, Fill};
+
+/// Frames of history kept for the graph.
+const HISTORY: usize = 120;
+/// Frames the update/draw numbers are averaged over, so they're readable.
+const AVERAGE_OVER: usize = 30;
+/// Frame time at the top of the graph, two frames at 60 FPS.
+const GRAPH_MAX_MS: f64 = 1000.0 / 30.0;
+
+const WIDTH: f32 = 260.0;
+const TEXT_SIZE: f32 = 18.0;
+const GRAPH_HEIGHT: i32 = 60;
+
+/// What one frame cost, in milliseconds.
+#[derive(Clone, Copy, Debug, Default)]
+struct FrameStats {
+    frame: f64,
+    update: f64,
+    draw: f64,
+}
+
+/// Debug numbers in the corner of the screen: FPS, a frame time graph, how
+/// long updating and drawing took, and how busy the game is.
+pub(crate) struct PerfOverlay {
+    pub visible: bool,
+    history: VecDeque<FrameStats>,
+    sprites: usize,
+    collision_checks: usize,
+    graph: BarGraph,
+}
+
+impl PerfOverlay {
+    pub(crate) fn new() -> PerfOverlay {
+        let mut graph = BarGraph::new(0, WIDTH as i32, 0, GRAPH_HEIGHT, HISTORY as i32);
+        graph.max_value = Some(GRAPH_MAX_MS);
+        graph.fill = Fill::Gradient {
+            base: Color::LIME,
+            tip: Color::RED,
+        };
+        PerfOverlay {
+            visible: false,
+            history: VecDeque::with_capacity(HISTORY),
+            sprites: 0,
+            collision_checks: 0,
+            graph,
+        }
+    }
+
+    pub(crate) fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Adds a finished frame. `frame_time` is raylib's, in seconds.
+    pub(crate) fn record(&mut self, frame_time: f32, update: Duration, draw: Duration) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(FrameStats {
+            frame: frame_time as f64 * 1000.0,
+            update: update.as_secs_f64() * 1000.0,
+            draw: draw.as_secs_f64() * 1000.0,
+        });
+        // one bar per frame, blank until the history fills up
+        let mut frame_times = vec![0.0; HISTORY - self.history.len()];
+        frame_times.extend(self.history.iter().map(|s| s.frame));
+        self.graph.update(&frame_times);
+    }
+
+    pub(crate) fn set_counts(&mut self, sprites: usize, collision_checks: usize) {
+        self.sprites = sprites;
+        self.collision_checks = collision_checks;
+    }
+
+    fn average(&self) -> FrameStats {
+        let recent: Vec<&FrameStats> = self.history.iter().rev().take(AVERAGE_OVER).collect();
+        let n = recent.len().max(1) as f64;
+        FrameStats {
+            frame: recent.iter().map(|s| s.frame).sum::<f64>() / n,
+            update: recent.iter().map(|s| s.update).sum::<f64>() / n,
+            draw: recent.iter().map(|s| s.draw).sum::<f64>() / n,
+        }
+    }
+
+    pub(crate) fn draw<T: RaylibDraw>(&mut self, d: &mut T, font: &Font, x: f32, y: f32) {
+        if !self.visible {
+            return;
+        }
+        let average = self.average();
+        let fps = if average.frame > 0.0 { 1000.0 / average.frame } else { 0.0 };
+        let lines = [
+            format!("FPS {:.0}  frame {:.1} ms", fps, average.frame),
+            format!("update {:.2} ms  draw {:.2} ms", average.update, average.draw),
+            format!("sprites {}  collision checks {}", self.sprites, self.collision_checks),
+        ];
+        let padding = 6.0;
+        let config = TextConfig {
+            spacing: 0.0,
+            tint: Color::WHITE,
+            paragraph_align: 0.0,
+            anchor_x: 0.0,
+            anchor_y: 0.0,
+            line_spacing: 1.0,
+        };
+        let lines: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+        let text_at = Vector2::new(x + padding, y + padding);
+        let text = layout_text(font, &lines, text_at, TEXT_SIZE, &config).bounds;
+        let text_height = text.height;
+        d.draw_rectangle_rec(
+            Rectangle::new(
+                x,
+                y,
+                WIDTH.max(text.width) + padding * 2.0,
+                text_height + GRAPH_HEIGHT as f32 + padding * 3.0,
+            ),
+            Color::BLACK.fade(0.7),
+        );
+        draw_text_anchored(d, font, lines, text_at, TEXT_SIZE, &config);
+
+        // newest frame on the right
+        let graph_bottom = y + padding * 2.0 + text_height + GRAPH_HEIGHT as f32;
+        self.graph.left = (x + padding) as i32;
+        self.graph.right = (x + padding + WIDTH) as i32;
+        self.graph.baseline = graph_bottom as i32;
+        self.graph.draw(d);
+        // where 60 FPS is
+        let budget = graph_bottom - GRAPH_HEIGHT as f32 * (1000.0 / 60.0 / GRAPH_MAX_MS) as f32;
+        d.draw_line_ex(
+            Vector2::new(x + padding, budget),
+            Vector2::new(x + padding + WIDTH, budget),
+            1.0,
+            Color::WHITE.fade(0.5),
+        );
+    }
+}
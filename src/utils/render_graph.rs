@@ -1,5 +1,177 @@
-use raylib::{color::Color, prelude::RaylibDraw};
+use raylib::prelude::*;
+use crate::utils::draw_text_anchor::{draw_text_anchored, TextConfig};
 
+/// Which way bars grow from the graph's baseline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Orientation {
+    Up,
+    Down,
+    /// Centered on the baseline, growing both ways.
+    Mirrored,
+}
+
+/// How values map to bar heights.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Scale {
+    Linear,
+    /// Logarithmic, so quiet values still show up next to loud ones.
+    Log,
+}
+
+/// How a graph picks one value per bar out of however many it's given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Resample {
+    /// Straight line between the two nearest values.
+    Linear,
+    /// Loudest value in the stretch each bar covers, so narrow peaks don't
+    /// fall between bars.
+    Max,
+    /// Mean of the values each bar covers.
+    Average,
+    /// Cubic B-spline. Smoother than the others, but doesn't quite reach
+    /// the peaks.
+    Cubic,
+    /// Curve through every value, without the corners of `Linear`.
+    CatmullRom,
+}
+
+/// Eases bars towards new values instead of jumping, over several updates.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Smoothing {
+    /// Fraction of the old height kept each update while a bar rises. 0
+    /// jumps straight up.
+    pub attack: f64,
+    /// Same while it falls.
+    pub decay: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Fill {
+    Solid(Color),
+    /// Fades from `base` at the baseline to `tip` at the end of the bar.
+    Gradient { base: Color, tip: Color },
+}
+
+/// A bar graph spread over `left..right`, bars growing from `baseline`.
+///
+/// Call `update` with new values, then `draw` as often as needed.
+#[derive(Clone, Debug)]
+pub(crate) struct BarGraph {
+    pub left: i32,
+    pub right: i32,
+    pub baseline: i32,
+    /// Height of a bar at `max_value`.
+    pub max_height: i32,
+    pub bars: i32,
+    pub gap: i32,
+    pub orientation: Orientation,
+    pub scale: Scale,
+    pub fill: Fill,
+    /// Value drawn at full height. `None` scales to the largest value given.
+    pub max_value: Option<f64>,
+    pub resample: Resample,
+    pub smoothing: Option<Smoothing>,
+    levels: Vec<f64>,
+}
+
+impl BarGraph {
+    pub(crate) fn new(left: i32, right: i32, baseline: i32, max_height: i32, bars: i32) -> BarGraph {
+        BarGraph {
+            left,
+            right,
+            baseline,
+            max_height,
+            bars,
+            gap: 0,
+            orientation: Orientation::Up,
+            scale: Scale::Linear,
+            fill: Fill::Solid(Color::BLACK),
+            max_value: None,
+            resample: Resample::Linear,
+            smoothing: None,
+            levels: Vec::new(),
+        }
+    }
+
+    /// Works out each bar's height from `values` (magnitudes are used, so
+    /// negative values count the same as positive ones).
+    pub(crate) fn update<V: Copy + Into<f64>>(&mut self, values: &[V]) {
+        let as_abs: Vec<f64> = values.iter().map(|v| (*v).into().abs()).collect();
+        let sampled = resample(&as_abs, self.bars.max(0) as usize, self.resample);
+        let max = self
+            .max_value
+            .unwrap_or_else(|| sampled.iter().cloned().fold(0.0, f64::max));
+        let targets = sampled.iter().map(|v| {
+            if max <= 0.0 {
+                return 0.0;
+            }
+            let level = (v / max).clamp(0.0, 1.0);
+            match self.scale {
+                Scale::Linear => level,
+                // 1 to 10 onto 0 to 1
+                Scale::Log => (1.0 + 9.0 * level).log10(),
+            }
+        });
+
+        let previous = std::mem::take(&mut self.levels);
+        self.levels = match self.smoothing {
+            Some(smoothing) if previous.len() == sampled.len() => targets
+                .zip(previous.iter())
+                .map(|(target, old)| {
+                    let keep = if target > *old { smoothing.attack } else { smoothing.decay };
+                    target + (old - target) * keep.clamp(0.0, 1.0)
+                })
+                .collect(),
+            _ => targets.collect(),
+        };
+    }
+
+    /// Each bar's height from 0 to 1, as of the last `update`.
+    pub(crate) fn levels(&self) -> &[f64] {
+        &self.levels
+    }
+
+    /// Where each bar is drawn, in whole pixels.
+    pub(crate) fn bar_rects(&self) -> Vec<Rectangle> {
+        self.levels
+            .iter()
+            .enumerate()
+            .map(|(i, level)| {
+                let (x, width) = bar_span(i as i32, self.left, self.right, self.bars, self.gap);
+                let height = (level * self.max_height as f64).round() as i32;
+                let y = match self.orientation {
+                    Orientation::Up => self.baseline - height,
+                    Orientation::Down => self.baseline,
+                    Orientation::Mirrored => self.baseline - height / 2,
+                };
+                Rectangle::new(x as f32, y as f32, width as f32, height as f32)
+            })
+            .collect()
+    }
+
+    pub(crate) fn draw<T: RaylibDraw>(&self, d: &mut T) {
+        for rect in self.bar_rects() {
+            let (x, y, width, height) = (rect.x as i32, rect.y as i32, rect.width as i32, rect.height as i32);
+            match (self.fill, self.orientation) {
+                (Fill::Solid(color), _) => d.draw_rectangle(x, y, width, height, color),
+                // gradients go top color first
+                (Fill::Gradient { base, tip }, Orientation::Up) => {
+                    d.draw_rectangle_gradient_v(x, y, width, height, tip, base)
+                }
+                (Fill::Gradient { base, tip }, Orientation::Down) => {
+                    d.draw_rectangle_gradient_v(x, y, width, height, base, tip)
+                }
+                (Fill::Gradient { base, tip }, Orientation::Mirrored) => {
+                    let upper = self.baseline - y;
+                    d.draw_rectangle_gradient_v(x, y, width, upper, tip, base);
+                    d.draw_rectangle_gradient_v(x, self.baseline, width, height - upper, base, tip);
+                }
+            }
+        }
+    }
+}
+
+/// Black bars hanging down from `y`, `max_height` tall at the largest value.
 pub fn render_graph<T: RaylibDraw>(d: &mut T, points: &Vec<i32>,
     y: i32,
     left_point: i32, right_point: i32,
@@ -7,32 +179,452 @@ pub fn render_graph<T: RaylibDraw>(d: &mut T, points: &Vec<i32>,
     bars: i32,
     gap: i32
 ) {
-    let as_abs: Vec<i32> = points.iter().map(|v| v.abs()).collect();
-    let width = right_point - left_point + gap;
-    let bar_width = width / bars;  // INCLUDING THE GAP
-    
-    for i in 0..bars {
-        d.draw_rectangle(left_point + bar_width * i, y, bar_width - gap, 
-            interpolate_vec(&as_abs, (i as f64/bars as f64) * as_abs.len() as f64) as i32,
-        Color::BLACK);
-    }
+    let mut graph = BarGraph::new(left_point, right_point, y, max_height, bars);
+    graph.gap = gap;
+    graph.orientation = Orientation::Down;
+    graph.update(points);
+    graph.draw(d);
+}
+
+/// Left edge and width of bar `i` out of `bars` spread over
+/// `left_point..right_point` with `gap` pixels between them. Edges are
+/// rounded so the bars always fill the range exactly.
+pub(crate) fn bar_span(i: i32, left_point: i32, right_point: i32, bars: i32, gap: i32) -> (i32, i32) {
+    let width = (right_point - left_point + gap) as f64;  // INCLUDING THE GAP
+    let bars = bars.max(1) as f64;
+    let start = (width * i as f64 / bars).round() as i32;
+    let end = (width * (i + 1) as f64 / bars).round() as i32;
+    (left_point + start, end - start - gap)
+}
 
+/// `count` values taken from `values` using `mode`.
+pub(crate) fn resample(values: &[f64], count: usize, mode: Resample) -> Vec<f64> {
+    let len = values.len();
+    if len == 0 {
+        return vec![0.0; count];
+    }
+    let per_value = len as f64 / count.max(1) as f64;
+    (0..count)
+        .map(|i| {
+            let idx = i as f64 * per_value;
+            match mode {
+                Resample::Linear => interpolate_vec(values, idx),
+                Resample::Max | Resample::Average => {
+                    // always at least the value under the bar, for when
+                    // there are more bars than values
+                    let first = (idx.floor() as usize).min(len - 1);
+                    let last = (((i + 1) as f64 * per_value).ceil() as usize).clamp(first + 1, len);
+                    let covered = &values[first..last];
+                    if mode == Resample::Max {
+                        covered.iter().cloned().fold(f64::MIN, f64::max)
+                    } else {
+                        covered.iter().sum::<f64>() / covered.len() as f64
+                    }
+                }
+                Resample::Cubic | Resample::CatmullRom => {
+                    let at = |j: isize| values[j.clamp(0, len as isize - 1) as usize];
+                    let base = idx.floor() as isize;
+                    let t = idx - idx.floor();
+                    let (p0, p1, p2, p3) = (at(base - 1), at(base), at(base + 1), at(base + 2));
+                    let (t2, t3) = (t * t, t * t * t);
+                    if mode == Resample::Cubic {
+                        ((1.0 - t).powi(3) * p0 + (3.0 * t3 - 6.0 * t2 + 4.0) * p1
+                            + (-3.0 * t3 + 3.0 * t2 + 3.0 * t + 1.0) * p2
+                            + t3 * p3)
+                            / 6.0
+                    } else {
+                        0.5 * (2.0 * p1
+                            + (p2 - p0) * t
+                            + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                            + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+                    }
+                }
+            }
+        })
+        .collect()
 }
 
-fn interpolate_vec(vec: &Vec<i32>, idx: f64) -> f64 {
+fn interpolate_vec(vec: &[f64], idx: f64) -> f64 {
     let vec_len = vec.len();
     if vec_len == 0 {
         return 0.0
     }
     if idx > (vec_len - 1) as f64 {
-        return (vec[vec_len - 1]) as f64;
+        return vec[vec_len - 1];
     }
     if idx < 0.0 {
-        return (vec[0]) as f64;
+        return vec[0];
     }
     let flo = idx.floor() as usize;
     let cei = idx.ceil() as usize;
     let progression = idx % 1.0;
-    (vec[cei] as f64) * progression + (vec[flo] as f64) * (1.0 - progression)
+    vec[cei] * progression + vec[flo] * (1.0 - progression)
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SeriesStyle {
+    Line,
+    /// A line with the space down to zero (or the nearest edge) filled in.
+    Area,
+    Scatter,
+}
+
+/// One set of (x, y) points on a `Chart`.
+#[derive(Clone, Debug)]
+pub(crate) struct Series {
+    pub name: String,
+    pub points: Vec<(f64, f64)>,
+    pub color: Color,
+    pub style: SeriesStyle,
+}
+
+impl Series {
+    pub(crate) fn new(name: &str, points: Vec<(f64, f64)>, color: Color, style: SeriesStyle) -> Series {
+        Series {
+            name: name.to_string(),
+            points,
+            color,
+            style,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Axis {
+    pub min: f64,
+    pub max: f64,
+    /// Roughly how many ticks to label, rounded to a tidy step.
+    pub ticks: usize,
+    pub label: String,
+}
+
+impl Axis {
+    pub(crate) fn new(label: &str) -> Axis {
+        Axis {
+            min: 0.0,
+            max: 1.0,
+            ticks: 5,
+            label: label.to_string(),
+        }
+    }
+
+    /// Distance between ticks: 1, 2 or 5 times a power of ten.
+    pub(crate) fn step(&self) -> f64 {
+        let raw = (self.max - self.min).abs() / self.ticks.max(1) as f64;
+        if raw <= 0.0 || !raw.is_finite() {
+            return 1.0;
+        }
+        let magnitude = 10f64.powf(raw.log10().floor());
+        let step = [1.0, 2.0, 5.0, 10.0]
+            .iter()
+            .map(|m| m * magnitude)
+            .find(|s| *s >= raw)
+            .unwrap_or(10.0 * magnitude);
+        return step;
+    }
+
+    /// Every tick value between `min` and `max`.
+    pub(crate) fn tick_values(&self) -> Vec<f64> {
+        let step = self.step();
+        let first = (self.min / step).ceil() as i64;
+        let last = (self.max / step + 1e-9).floor() as i64;
+        (first..=last).map(|i| i as f64 * step).collect()
+    }
+
+    fn format(&self, value: f64) -> String {
+        let decimals = (-self.step().log10()).ceil().max(0.0) as usize;
+        format!("{:.*}", decimals, value)
+    }
+
+    /// Widens the range to cover `values`, snapped out to whole ticks.
+    fn fit(&mut self, values: impl Iterator<Item = f64>) {
+        let (min, max) = values.fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+        if min > max {
+            return;
+        }
+        self.min = min;
+        self.max = if max > min { max } else { min + 1.0 };
+        let step = self.step();
+        self.min = (self.min / step).floor() * step;
+        self.max = (self.max / step).ceil() * step;
+    }
+}
+
+/// Line, area and scatter plots over shared axes, drawn inside `bounds`
+/// with the tick labels just outside it.
+#[derive(Clone, Debug)]
+pub(crate) struct Chart {
+    pub bounds: Rectangle,
+    pub x: Axis,
+    pub y: Axis,
+    pub font_size: f32,
+    pub axis_color: Color,
+    pub line_thickness: f32,
+    pub point_radius: f32,
+    pub legend: bool,
+}
+
+impl Chart {
+    pub(crate) fn new(bounds: Rectangle) -> Chart {
+        Chart {
+            bounds,
+            x: Axis::new(""),
+            y: Axis::new(""),
+            font_size: 16.0,
+            axis_color: Color::WHITE,
+            line_thickness: 2.0,
+            point_radius: 3.0,
+            legend: true,
+        }
+    }
+
+    /// Sets both axes to cover every point of `series`.
+    pub(crate) fn fit(&mut self, series: &[Series]) {
+        let points = || series.iter().flat_map(|s| s.points.iter());
+        self.x.fit(points().map(|p| p.0));
+        self.y.fit(points().map(|p| p.1));
+    }
+
+    /// Where a value lands on screen. Values outside the axes land outside
+    /// `bounds`.
+    pub(crate) fn to_screen(&self, x: f64, y: f64) -> Vector2 {
+        let fx = (x - self.x.min) / (self.x.max - self.x.min).max(f64::EPSILON);
+        let fy = (y - self.y.min) / (self.y.max - self.y.min).max(f64::EPSILON);
+        Vector2::new(
+            self.bounds.x + fx as f32 * self.bounds.width,
+            self.bounds.y + self.bounds.height - fy as f32 * self.bounds.height,
+        )
+    }
+
+    pub(crate) fn draw<T: RaylibDraw>(&self, d: &mut T, font: &Font, series: &[Series]) {
+        self.draw_axes(d, font);
+        for s in series {
+            self.draw_series(d, s);
+        }
+        if self.legend {
+            self.draw_legend(d, font, series);
+        }
+    }
+
+    fn label_config(&self, anchor_x: f32, anchor_y: f32) -> TextConfig {
+        TextConfig {
+            spacing: 0.0,
+            tint: self.axis_color,
+            paragraph_align: anchor_x,
+            anchor_x,
+            anchor_y,
+            line_spacing: 1.0,
+        }
+    }
+
+    fn draw_axes<T: RaylibDraw>(&self, d: &mut T, font: &Font) {
+        let b = self.bounds;
+        let (left, bottom) = (b.x, b.y + b.height);
+        let tick = self.font_size * 0.4;
+        d.draw_line_ex(Vector2::new(left, b.y), Vector2::new(left, bottom), 1.0, self.axis_color);
+        d.draw_line_ex(Vector2::new(left, bottom), Vector2::new(b.x + b.width, bottom), 1.0, self.axis_color);
+
+        let below = self.label_config(0.5, 0.0);
+        for value in self.x.tick_values() {
+            let x = self.to_screen(value, self.y.min).x;
+            d.draw_line_ex(Vector2::new(x, bottom), Vector2::new(x, bottom + tick), 1.0, self.axis_color);
+            let text = self.x.format(value);
+            draw_text_anchored(d, font, vec![text.as_str()], Vector2::new(x, bottom + tick), self.font_size, &below);
+        }
+        let beside = self.label_config(1.0, 0.5);
+        for value in self.y.tick_values() {
+            let y = self.to_screen(self.x.min, value).y;
+            d.draw_line_ex(Vector2::new(left - tick, y), Vector2::new(left, y), 1.0, self.axis_color);
+            let text = self.y.format(value);
+            draw_text_anchored(d, font, vec![text.as_str()], Vector2::new(left - tick * 1.5, y), self.font_size, &beside);
+        }
+
+        if !self.x.label.is_empty() {
+            let at = Vector2::new(left + b.width / 2.0, bottom + tick + self.font_size * 1.2);
+            draw_text_anchored(d, font, vec![self.x.label.as_str()], at, self.font_size, &below);
+        }
+        if !self.y.label.is_empty() {
+            let at = Vector2::new(left, b.y - self.font_size * 0.5);
+            draw_text_anchored(d, font, vec![self.y.label.as_str()], at, self.font_size, &self.label_config(0.5, 1.0));
+        }
+    }
+
+    fn draw_series<T: RaylibDraw>(&self, d: &mut T, series: &Series) {
+        let points: Vec<Vector2> = series.points.iter().map(|(x, y)| self.to_screen(*x, *y)).collect();
+        match series.style {
+            SeriesStyle::Scatter => {
+                for p in points.iter() {
+                    d.draw_circle_v(*p, self.point_radius, series.color);
+                }
+            }
+            SeriesStyle::Line | SeriesStyle::Area => {
+                if series.style == SeriesStyle::Area {
+                    let zero = self.to_screen(0.0, 0.0f64.clamp(self.y.min, self.y.max)).y;
+                    let fill = series.color.fade(0.35);
+                    for pair in points.windows(2) {
+                        // wound the same way whichever side of zero the line is on
+                        let (top0, bottom0) = (pair[0].y.min(zero), pair[0].y.max(zero));
+                        let (top1, bottom1) = (pair[1].y.min(zero), pair[1].y.max(zero));
+                        let tl = Vector2::new(pair[0].x, top0);
+                        let bl = Vector2::new(pair[0].x, bottom0);
+                        let br = Vector2::new(pair[1].x, bottom1);
+                        let tr = Vector2::new(pair[1].x, top1);
+                        d.draw_triangle(tl, bl, br, fill);
+                        d.draw_triangle(tl, br, tr, fill);
+                    }
+                }
+                for pair in points.windows(2) {
+                    d.draw_line_ex(pair[0], pair[1], self.line_thickness, series.color);
+                }
+            }
+        }
+    }
+
+    /// Names and colors of every series, stacked in the top right corner.
+    fn draw_legend<T: RaylibDraw>(&self, d: &mut T, font: &Font, series: &[Series]) {
+        let config = self.label_config(1.0, 0.5);
+        let swatch = self.font_size * 0.6;
+        let right = self.bounds.x + self.bounds.width - swatch;
+        for (i, s) in series.iter().enumerate() {
+            let y = self.bounds.y + self.font_size * (i as f32 + 0.75);
+            d.draw_rectangle_v(Vector2::new(right, y - swatch / 2.0), Vector2::new(swatch, swatch), s.color);
+            draw_text_anchored(d, font, vec![s.name.as_str()], Vector2::new(right - swatch * 0.5, y), self.font_size, &config);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bars_tile_the_range_exactly() {
+        for (left, right) in [(0, 100), (10, 343), (-7, 633), (5, 1006)] {
+            for bars in [1, 3, 7, 13, 64, 100] {
+                for gap in [0, 1, 3] {
+                    let spans: Vec<(i32, i32)> = (0..bars).map(|i| bar_span(i, left, right, bars, gap)).collect();
+                    let case = format!("{}..{}, {} bars, gap {}", left, right, bars, gap);
+                    assert_eq!(spans[0].0, left, "{}", case);
+                    let (x, w) = spans[spans.len() - 1];
+                    assert_eq!(x + w, right, "{}", case);
+                    for pair in spans.windows(2) {
+                        assert_eq!(pair[0].0 + pair[0].1 + gap, pair[1].0, "{}", case);
+                    }
+                    let narrowest = spans.iter().map(|s| s.1).min().unwrap();
+                    let widest = spans.iter().map(|s| s.1).max().unwrap();
+                    assert!(widest - narrowest <= 1, "{}", case);
+                }
+            }
+        }
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn zero_bars_counts_as_one() {
+        assert_eq!(bar_span(0, 10, 110, 0, 2), (10, 100));
+    }
+
+    const VALUES: [f64; 8] = [0.0, 4.0, 8.0, 4.0, 0.0, 0.0, 2.0, 6.0];
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn pooling_covers_every_value() {
+        assert_close(&resample(&VALUES, 4, Resample::Max), &[4.0, 8.0, 0.0, 6.0]);
+        assert_close(&resample(&VALUES, 4, Resample::Average), &[2.0, 6.0, 0.0, 4.0]);
+        // uneven stretches overlap rather than skip a value
+        assert_close(&resample(&VALUES, 3, Resample::Max), &[8.0, 8.0, 6.0]);
+        // more bars than values repeats the value under each bar
+        let expected: Vec<f64> = VALUES.iter().flat_map(|v| [*v, *v]).collect();
+        assert_close(&resample(&VALUES, 16, Resample::Max), &expected);
+        assert_close(&resample(&VALUES, 16, Resample::Average), &expected);
+    }
+
+    #[test]
+    fn linear_interpolates_between_neighbours() {
+        assert_close(&resample(&VALUES, 4, Resample::Linear), &[0.0, 8.0, 0.0, 2.0]);
+        assert_close(&resample(&VALUES[..3], 6, Resample::Linear), &[0.0, 2.0, 4.0, 6.0, 8.0, 8.0]);
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_the_values() {
+        assert_close(&resample(&VALUES, 8, Resample::CatmullRom), &VALUES);
+        // halfway is (-p0 + 9p1 + 9p2 - p3) / 16, with the ends repeated
+        let halves = resample(&VALUES, 16, Resample::CatmullRom);
+        assert_close(&[halves[1], halves[3], halves[15]], &[28.0 / 16.0, 104.0 / 16.0, 100.0 / 16.0]);
+    }
+
+    #[test]
+    fn cubic_smooths_without_reaching_the_peaks() {
+        // on a value it's (p0 + 4p1 + p2) / 6
+        assert_close(&resample(&VALUES, 4, Resample::Cubic), &[4.0 / 6.0, 40.0 / 6.0, 4.0 / 6.0, 14.0 / 6.0]);
+        let halves = resample(&VALUES, 16, Resample::Cubic);
+        assert!((halves[1] - 12.5 / 6.0).abs() < 1e-9, "{}", halves[1]);
+    }
+
+    #[test]
+    fn every_mode_keeps_a_flat_line_flat() {
+        for mode in [Resample::Linear, Resample::Max, Resample::Average, Resample::Cubic, Resample::CatmullRom] {
+            for count in [1, 3, 8, 20] {
+                assert_close(&resample(&[3.0; 8], count, mode), &vec![3.0; count]);
+            }
+            assert_close(&resample(&[], 4, mode), &[0.0; 4]);
+        }
+    }
+
+    fn axis(min: f64, max: f64, ticks: usize) -> Axis {
+        return Axis {
+            min,
+            max,
+            ticks,
+            label: String::new(),
+        };
+    }
+
+    #[test]
+    fn axis_steps_are_one_two_or_five() {
+        for (min, max, ticks, step) in [
+            (0.0, 1.0, 5, 0.2),
+            (0.0, 10.0, 5, 2.0),
+            (0.0, 7.0, 5, 2.0),
+            (0.0, 30.0, 5, 10.0),
+            (0.0, 100.0, 4, 50.0),
+            (0.0, 0.3, 5, 0.1),
+            (-3.0, 3.0, 6, 1.0),
+            (10.0, 0.0, 5, 2.0),
+            (0.0, 1.0, 0, 1.0),
+            (2.0, 2.0, 5, 1.0),
+        ] {
+            let actual = axis(min, max, ticks).step();
+            assert!((actual - step).abs() < 1e-12, "{}..{} in {}: {}", min, max, ticks, actual);
+        }
+    }
+
+    #[test]
+    fn ticks_fall_on_whole_steps_inside_the_range() {
+        assert_close(&axis(0.0, 1.0, 5).tick_values(), &[0.0, 0.2, 0.4, 0.6, 0.8, 1.0]);
+        assert_close(&axis(-3.5, 7.2, 5).tick_values(), &[0.0, 5.0]);
+        assert_close(&axis(-10.0, 10.0, 4).tick_values(), &[-10.0, -5.0, 0.0, 5.0, 10.0]);
+        assert_close(&axis(0.05, 0.95, 4).tick_values(), &[0.5]);
+    }
+
+    #[test]
+    fn fit_snaps_out_to_whole_ticks() {
+        let mut x = axis(0.0, 1.0, 5);
+        x.fit([3.0, 17.0, 9.0].into_iter());
+        assert_eq!((x.min, x.max), (0.0, 20.0));
+        assert_eq!((x.format(5.0), x.format(15.0)), ("5".to_string(), "15".to_string()));
+
+        x.fit([4.0].into_iter());
+        assert!((x.min - 4.0).abs() < 1e-9 && (x.max - 5.0).abs() < 1e-9, "{}..{}", x.min, x.max);
+        assert_eq!(x.format(4.2), "4.2");
+
+        x.fit(std::iter::empty());
+        assert!((x.min - 4.0).abs() < 1e-9, "{}", x.min);
+    }
+}
@@ -0,0 +1,254 @@
+use std::f64::consts::PI;
+
+/// Zero crossings of the sinc on each side of the output sample. Higher is
+/// sharper (less aliasing and ringing) but slower.
+const HALF_TAPS: usize = 64;
+
+/// How far below the new Nyquist frequency the low-pass cuts off, leaving
+/// room for the window's transition band.
+const ROLLOFF: f64 = 0.95;
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        return 1.0;
+    }
+    let px = PI * x;
+    px.sin() / px
+}
+
+/// Blackman window over -1 to 1, zero outside.
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let t = (x + 1.0) / 2.0;
+    0.42 - 0.5 * (2.0 * PI * t).cos() + 0.08 * (4.0 * PI * t).cos()
+}
+
+/// Most filter phases precomputed. Every common pair of rates needs far
+/// fewer (48k to 44.1k is 147), odd ones snap to the nearest of these.
+const MAX_PHASES: usize = 4096;
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        return a;
+    }
+    gcd(b, a % b)
+}
+
+/// The resampling filter worked out once for every fractional position an
+/// output sample can fall on, so converting is only multiply-adds.
+///
+/// Output sample `n` sits at input position `n * down / up`. Its integer
+/// part picks the input samples and the fraction picks the row of `table`.
+struct Kernel {
+    up: usize,
+    down: usize,
+    phases: usize,
+    /// Taps either side of the output sample, rows are `2 * half` long.
+    half: usize,
+    table: Vec<f64>,
+}
+
+impl Kernel {
+    fn new(from: u32, to: u32) -> Kernel {
+        let g = gcd(from as usize, to as usize);
+        let (up, down) = (to as usize / g, from as usize / g);
+        let phases = up.min(MAX_PHASES);
+        let cutoff = (up as f64 / down as f64).min(1.0) * ROLLOFF;
+        // filter half-width in input samples
+        let reach = HALF_TAPS as f64 / cutoff;
+        let half = reach.ceil() as usize;
+
+        let mut table = Vec::with_capacity(phases * half * 2);
+        for phase in 0..phases {
+            let frac = phase as f64 / phases as f64;
+            for j in 0..half * 2 {
+                // distance from the output sample to input sample `base + j + 1 - half`
+                let d = frac + half as f64 - 1.0 - j as f64;
+                table.push(cutoff * sinc(cutoff * d) * blackman(d / reach));
+            }
+        }
+        Kernel {
+            up,
+            down,
+            phases,
+            half,
+            table,
+        }
+    }
+
+    /// Input index of the first tap and the row of weights for output `n`.
+    fn taps(&self, n: usize) -> (isize, &[f64]) {
+        let pos = n as u64 * self.down as u64;
+        let mut base = (pos / self.up as u64) as usize;
+        let rem = (pos % self.up as u64) as usize;
+        let mut phase = (rem * self.phases + self.up / 2) / self.up;
+        if phase == self.phases {
+            base += 1;
+            phase = 0;
+        }
+        let row = self.half * 2;
+        let first = base as isize + 1 - self.half as isize;
+        (first, &self.table[phase * row..(phase + 1) * row])
+    }
+}
+
+/// Converts mono `samples` recorded at `from` Hz to `to` Hz with a
+/// Blackman-windowed sinc filter.
+///
+/// When downsampling the filter is stretched so it also removes everything
+/// above the new Nyquist frequency. Output values are clamped to
+/// `-full_scale..full_scale`.
+pub(crate) fn resample(samples: &[i32], from: u32, to: u32, full_scale: f64) -> Vec<i32> {
+    if from == to || samples.is_empty() || from == 0 || to == 0 {
+        return samples.to_vec();
+    }
+    let kernel = Kernel::new(from, to);
+    let out_len = (samples.len() as f64 * to as f64 / from as f64).round() as usize;
+    let max = full_scale - 1.0;
+
+    let mut out = Vec::with_capacity(out_len);
+    for n in 0..out_len {
+        let (first, weights) = kernel.taps(n);
+        // only the edges of the track have taps hanging off either end
+        let skip = (-first).max(0) as usize;
+        let start = (first + skip as isize) as usize;
+        let acc: f64 = samples
+            .iter()
+            .skip(start)
+            .zip(weights.iter().skip(skip))
+            .map(|(s, w)| *s as f64 * w)
+            .sum();
+        out.push(acc.round().clamp(-full_scale, max) as i32);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::audio::WavAudio;
+    use std::f64::consts::TAU;
+
+    const FULL_SCALE: f64 = 32768.0;
+
+    /// Half scale linear sweep from `f0` to `f1` Hz.
+    fn sweep(f0: f64, f1: f64, rate: u32, secs: f64) -> Vec<i32> {
+        let n = (secs * rate as f64) as usize;
+        (0..n)
+            .map(|i| {
+                let t = i as f64 / rate as f64;
+                let phase = TAU * (f0 * t + (f1 - f0) * t * t / (2.0 * secs));
+                (phase.sin() * FULL_SCALE * 0.5).round() as i32
+            })
+            .collect()
+    }
+
+    /// Frequency from rising zero crossings, ignoring the filter's edges.
+    fn frequency(samples: &[i32], rate: u32) -> f64 {
+        let middle = &samples[samples.len() / 8..samples.len() * 7 / 8];
+        let crossings: Vec<usize> = (1..middle.len()).filter(|&i| middle[i - 1] < 0 && middle[i] >= 0).collect();
+        let periods = (crossings.len() - 1) as f64;
+        let span = (crossings[crossings.len() - 1] - crossings[0]) as f64;
+        periods * rate as f64 / span
+    }
+
+    fn rms(samples: &[i32]) -> f64 {
+        let middle = &samples[samples.len() / 8..samples.len() * 7 / 8];
+        (middle.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / middle.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn length_follows_the_ratio() {
+        let input = sweep(440.0, 440.0, 44100, 0.5);
+        assert_eq!(resample(&input, 44100, 48000, FULL_SCALE).len(), 24000);
+        let input = sweep(440.0, 440.0, 48000, 0.5);
+        assert_eq!(resample(&input, 48000, 44100, FULL_SCALE).len(), 22050);
+    }
+
+    #[test]
+    fn sine_keeps_its_frequency() {
+        for (from, to) in [(44100, 48000), (48000, 44100)] {
+            let input = sweep(1000.0, 1000.0, from, 0.5);
+            let output = resample(&input, from, to, FULL_SCALE);
+            let measured = frequency(&output, to);
+            assert!((measured - 1000.0).abs() < 1.0, "{} -> {}: {}Hz", from, to, measured);
+            let gain = rms(&output) / rms(&input);
+            assert!((gain - 1.0).abs() < 0.01, "{} -> {}: gain {}", from, to, gain);
+        }
+    }
+
+    #[test]
+    fn passband_sweep_is_kept() {
+        for (from, to) in [(44100, 48000), (48000, 44100)] {
+            let input = sweep(100.0, 18000.0, from, 0.5);
+            let gain = rms(&resample(&input, from, to, FULL_SCALE)) / rms(&input);
+            assert!((gain - 1.0).abs() < 0.02, "{} -> {}: gain {}", from, to, gain);
+        }
+    }
+
+    #[test]
+    fn sweep_above_new_nyquist_is_removed() {
+        // between 44.1k's Nyquist and 48k's
+        let input = sweep(22500.0, 23800.0, 48000, 0.5);
+        let gain = rms(&resample(&input, 48000, 44100, FULL_SCALE)) / rms(&input);
+        assert!(gain < 0.01, "gain {}", gain);
+    }
+
+    /// The filter evaluated directly for every tap, what `Kernel` tabulates.
+    fn direct(samples: &[i32], from: u32, to: u32) -> Vec<f64> {
+        let ratio = to as f64 / from as f64;
+        let cutoff = ratio.min(1.0) * ROLLOFF;
+        let reach = HALF_TAPS as f64 / cutoff;
+        let out_len = (samples.len() as f64 * ratio).round() as usize;
+        (0..out_len)
+            .map(|n| {
+                let t = n as f64 / ratio;
+                let first = (t - reach).ceil().max(0.0) as usize;
+                let last = ((t + reach).floor() as usize).min(samples.len() - 1);
+                (first..=last)
+                    .map(|k| {
+                        let d = t - k as f64;
+                        samples[k] as f64 * cutoff * sinc(cutoff * d) * blackman(d / reach)
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn table_matches_the_direct_filter() {
+        let input = sweep(100.0, 15000.0, 48000, 0.05);
+        // 44101 has more phases than are kept, so it's off by a little more
+        // than rounding
+        for (from, to, tolerance) in [(48000, 44100, 1.0), (44100, 48000, 1.0), (22050, 44100, 1.0), (44100, 44101, 8.0)] {
+            let expected = direct(&input, from, to);
+            let output = resample(&input, from, to, FULL_SCALE);
+            assert_eq!(output.len(), expected.len());
+            let worst = output.iter().zip(expected.iter()).map(|(a, b)| (*a as f64 - b).abs()).fold(0.0, f64::max);
+            assert!(worst <= tolerance, "{} -> {}: off by {}", from, to, worst);
+        }
+    }
+
+    #[test]
+    fn table_stays_small() {
+        let kernel = Kernel::new(48000, 44100);
+        assert_eq!((kernel.up, kernel.down, kernel.phases), (147, 160, 147));
+        // one row per phase, no more taps than the filter is wide
+        let reach = HALF_TAPS as f64 / (147.0 / 160.0 * ROLLOFF);
+        assert_eq!(kernel.half, reach.ceil() as usize);
+        assert_eq!(kernel.table.len(), 147 * kernel.half * 2);
+
+        let kernel = Kernel::new(44100, 44101);
+        assert_eq!(kernel.phases, MAX_PHASES);
+        assert_eq!(kernel.table.len(), MAX_PHASES * kernel.half * 2);
+    }
+
+    #[test]
+    fn zero_rate_is_rejected() {
+        let audio = WavAudio::from_samples(vec![0; 100], 44100, 16);
+        assert!(audio.resampled(0).is_err());
+        assert_eq!(audio.resampled(48000).unwrap().sample_rate(), 48000);
+    }
+}
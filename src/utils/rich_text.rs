@@ -0,0 +1,274 @@
+use raylib::prelude::*;
+use crate::utils::draw_text_anchor::{layout_text, TextConfig};
+
+/// How far `[shake]` text jitters, as a fraction of the font size.
+const SHAKE_AMOUNT: f32 = 0.04;
+/// Shaking text only moves this many times a second, every frame is too much.
+const SHAKE_RATE: f32 = 30.0;
+/// How far `[wave]` text bobs, as a fraction of the font size.
+const WAVE_AMOUNT: f32 = 0.08;
+/// Radians per second.
+const WAVE_SPEED: f32 = 6.0;
+/// Phase between neighbouring characters, in radians.
+const WAVE_STEP: f32 = 0.6;
+
+/// The style a span of text is drawn with. `color` is `None` for the
+/// config's tint.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Style {
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub shake: bool,
+    pub wave: bool,
+}
+
+/// A run of text with one style, markup removed.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+fn named_color(name: &str) -> Option<Color> {
+    let color = match name.to_ascii_lowercase().as_str() {
+        "white" => Color::WHITE,
+        "black" => Color::BLACK,
+        "gray" | "grey" => Color::GRAY,
+        "red" => Color::RED,
+        "orange" => Color::ORANGE,
+        "yellow" => Color::YELLOW,
+        "gold" => Color::GOLD,
+        "green" => Color::GREEN,
+        "lime" => Color::LIME,
+        "blue" => Color::BLUE,
+        "skyblue" => Color::SKYBLUE,
+        "purple" => Color::PURPLE,
+        "pink" => Color::PINK,
+        "brown" => Color::BROWN,
+        hex => {
+            let hex = hex.strip_prefix('#')?;
+            if hex.len() != 6 {
+                return None;
+            }
+            return Color::from_hex(hex).ok();
+        }
+    };
+    return Some(color);
+}
+
+/// Splits a line with markup into styled spans.
+///
+/// Tags are `[color=yellow]`/`[color=#ffcc00]`, `[b]`, `[shake]` and
+/// `[wave]`, each closed by `[/color]`, `[/b]` and so on. Tags nest, and
+/// anything in brackets that isn't a tag is kept as text.
+pub(crate) fn parse_markup(line: &str) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut colors: Vec<Color> = Vec::new();
+    let mut style = Style::default();
+    let mut text = String::new();
+    let mut rest = line;
+
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find(']').map(|c| open + c) else {
+            break;
+        };
+        let tag = &rest[open + 1..close];
+        let mut next = style;
+        let known = match tag {
+            "b" => { next.bold = true; true }
+            "/b" => { next.bold = false; true }
+            "shake" => { next.shake = true; true }
+            "/shake" => { next.shake = false; true }
+            "wave" => { next.wave = true; true }
+            "/wave" => { next.wave = false; true }
+            "/color" => {
+                colors.pop();
+                next.color = colors.last().copied();
+                true
+            }
+            _ => match tag.strip_prefix("color=").and_then(named_color) {
+                Some(color) => {
+                    colors.push(color);
+                    next.color = Some(color);
+                    true
+                }
+                None => false,
+            },
+        };
+        if !known {
+            text.push_str(&rest[..=open]);
+            rest = &rest[open + 1..];
+            continue;
+        }
+        text.push_str(&rest[..open]);
+        if next != style && !text.is_empty() {
+            spans.push(Span { text: std::mem::take(&mut text), style });
+        }
+        style = next;
+        rest = &rest[close + 1..];
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        spans.push(Span { text, style });
+    }
+    return spans;
+}
+
+/// Cheap repeatable noise in -1..1, so shaking doesn't need an rng.
+fn jitter(seed: f32) -> f32 {
+    (seed.sin() * 43758.547).rem_euclid(1.0) * 2.0 - 1.0
+}
+
+/// Like `draw_text_anchored`, but each line can have markup (see
+/// `parse_markup`). Lines are laid out by their text without the markup, so
+/// alignment and anchoring work the same. `time` is in seconds and drives
+/// `[shake]` and `[wave]`.
+pub(crate) fn draw_rich_text_anchored<T: RaylibDraw>(
+    d: &mut T,
+    custom_font: &Font,
+    lines: Vec<&str>,
+    position: impl Into<Vector2>,
+    font_size: f32,
+    config: &TextConfig,
+    time: f32,
+) {
+    let parsed: Vec<Vec<Span>> = lines.iter().map(|l| parse_markup(l)).collect();
+    let plain: Vec<String> = parsed.iter().map(|spans| spans.iter().map(|s| s.text.as_str()).collect()).collect();
+    let plain_refs: Vec<&str> = plain.iter().map(|l| l.as_str()).collect();
+    let layout = layout_text(custom_font, &plain_refs, position, font_size, config);
+    let shake_frame = (time * SHAKE_RATE).floor();
+    // fake bold, the font only has one weight
+    let bold_offset = (font_size / 32.0).max(1.0);
+
+    for (line_idx, (spans, line_pos)) in parsed.iter().zip(layout.lines).enumerate() {
+        let line = &plain[line_idx];
+        let mut byte_at = 0;
+        let mut char_idx = 0;
+        for span in spans {
+            let color = span.style.color.unwrap_or(config.tint);
+            for (offset, ch) in span.text.char_indices() {
+                let start = byte_at + offset;
+                let x = if start == 0 {
+                    0.0
+                } else {
+                    custom_font.measure_text(&line[..start], font_size, config.spacing).x + config.spacing
+                };
+                let mut at = Vector2::new(line_pos.x + x, line_pos.y);
+                if span.style.shake {
+                    let seed = shake_frame + char_idx as f32 * 17.0 + line_idx as f32 * 131.0;
+                    at.x += jitter(seed) * font_size * SHAKE_AMOUNT;
+                    at.y += jitter(seed + 0.5) * font_size * SHAKE_AMOUNT;
+                }
+                if span.style.wave {
+                    at.y += (time * WAVE_SPEED - char_idx as f32 * WAVE_STEP).sin() * font_size * WAVE_AMOUNT;
+                }
+                let glyph = &line[start..start + ch.len_utf8()];
+                d.draw_text_ex(custom_font, glyph, at, font_size, config.spacing, color);
+                if span.style.bold {
+                    d.draw_text_ex(custom_font, glyph, at + Vector2::new(bold_offset, 0.0), font_size, config.spacing, color);
+                }
+                char_idx += 1;
+            }
+            byte_at += span.text.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, style: Style) -> Span {
+        return Span {
+            text: text.to_string(),
+            style,
+        };
+    }
+
+    fn colored(color: Color) -> Style {
+        return Style {
+            color: Some(color),
+            ..Style::default()
+        };
+    }
+
+    fn bold() -> Style {
+        return Style {
+            bold: true,
+            ..Style::default()
+        };
+    }
+
+    #[test]
+    fn plain_text_is_one_span() {
+        assert_eq!(parse_markup("hello"), vec![span("hello", Style::default())]);
+        assert!(parse_markup("").is_empty());
+    }
+
+    #[test]
+    fn tags_nest() {
+        let bold_wave = Style { wave: true, ..bold() };
+        assert_eq!(
+            parse_markup("a[b]b[wave]c[/wave]d[/b]e"),
+            vec![
+                span("a", Style::default()),
+                span("b", bold()),
+                span("c", bold_wave),
+                span("d", bold()),
+                span("e", Style::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_tags_run_to_the_end() {
+        let shake = Style {
+            shake: true,
+            ..Style::default()
+        };
+        assert_eq!(parse_markup("[shake]uh oh"), vec![span("uh oh", shake)]);
+        assert_eq!(parse_markup("[color=red]x[b]"), vec![span("x", colored(Color::RED))]);
+    }
+
+    #[test]
+    fn closing_a_color_restores_the_outer_one() {
+        assert_eq!(
+            parse_markup("[color=red]r[color=blue]b[/color]r[/color]w[/color]w"),
+            vec![
+                span("r", colored(Color::RED)),
+                span("b", colored(Color::BLUE)),
+                span("r", colored(Color::RED)),
+                span("ww", Style::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn hex_colors() {
+        let spans = parse_markup("[color=#FFCC00]gold");
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].style.color.is_some());
+        assert_eq!(parse_markup("[color=#ffffff]x")[0].style, colored(Color::from_hex("ffffff").unwrap()));
+        // wrong length or no # isn't a color
+        assert_eq!(parse_markup("[color=#fff]x"), vec![span("[color=#fff]x", Style::default())]);
+        assert_eq!(parse_markup("[color=ffcc00]x"), vec![span("[color=ffcc00]x", Style::default())]);
+    }
+
+    #[test]
+    fn unknown_brackets_are_kept_as_text() {
+        assert_eq!(
+            parse_markup("press [enter] or [color=nope]x[b]!"),
+            vec![
+                span("press [enter] or [color=nope]x", Style::default()),
+                span("!", bold()),
+            ]
+        );
+        assert_eq!(parse_markup("[[b]]"), vec![span("[", Style::default()), span("]", bold())]);
+        assert_eq!(parse_markup("half [open"), vec![span("half [open", Style::default())]);
+    }
+
+    #[test]
+    fn empty_styles_make_no_spans() {
+        assert_eq!(parse_markup("[b][/b][wave][/wave]x"), vec![span("x", Style::default())]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use rand::Rng;
+use raylib::prelude::*;
+use crate::utils::audio::WavAudio;
+use crate::utils::effects::EffectChain;
+use crate::utils::loudness::trim_silence;
+use crate::utils::synth::{render, to_wav_bytes, SynthParams};
+
+/// Anything quieter than this at the ends of a processed sound is cut.
+const TRIM_DB: f64 = -60.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum SoundCategory {
+    Sfx,
+    Ui,
+    Music,
+}
+
+/// Volume knobs, all from 0 to 1. The volume a sound ends up playing at is
+/// master * category * the sound's own volume.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SoundSettings {
+    pub master_volume: f32,
+    pub sfx_volume: f32,
+    pub ui_volume: f32,
+    pub music_volume: f32,
+    pub muted: bool,
+}
+
+impl Default for SoundSettings {
+    fn default() -> Self {
+        SoundSettings {
+            master_volume: 1.0,
+            sfx_volume: 1.0,
+            ui_volume: 1.0,
+            music_volume: 1.0,
+            muted: false,
+        }
+    }
+}
+
+impl SoundSettings {
+    pub(crate) fn category_volume(&self, category: SoundCategory) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        let cat = match category {
+            SoundCategory::Sfx => self.sfx_volume,
+            SoundCategory::Ui => self.ui_volume,
+            SoundCategory::Music => self.music_volume,
+        };
+        return self.master_volume * cat;
+    }
+}
+
+/// How much a sound is randomly detuned/attenuated each time it plays, so
+/// repeated hits don't sound identical.
+/// `pitch` and `volume` are +- ranges around 1.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Variation {
+    pub pitch: f32,
+    pub volume: f32,
+}
+
+/// How far sounds at the screen edges are panned, 1 is hard left/right.
+const STEREO_WIDTH: f32 = 0.7;
+/// How much quieter a sound at the screen edge is than one in the middle.
+const EDGE_ATTENUATION: f32 = 0.2;
+
+/// Works out raylib pan and a volume multiplier for a sound at `x` on a
+/// screen `width` pixels wide. Sounds off screen keep getting quieter the
+/// further away they are.
+pub(crate) fn spatialize(x: f32, width: f32) -> (f32, f32) {
+    // -1 at the left edge, 1 at the right
+    let offset = (x / width.max(1.0)) * 2.0 - 1.0;
+    let on_screen = offset.clamp(-1.0, 1.0);
+    // raylib 5.0 pans fully left at 1 and fully right at 0
+    let pan = 0.5 - on_screen * STEREO_WIDTH * 0.5;
+    let outside = offset.abs() - 1.0;
+    let mut volume = 1.0 - EDGE_ATTENUATION * on_screen.abs();
+    if outside > 0.0 {
+        volume /= 1.0 + outside * 4.0;
+    }
+    (pan, volume)
+}
+
+struct SoundEntry<'aud> {
+    category: SoundCategory,
+    // one copy of the sound per voice so several can overlap
+    voices: Vec<Sound<'aud>>,
+    next_voice: usize,
+    volume: f32,
+    variation: Variation,
+}
+
+/// All the game's sound effects, looked up by name.
+pub(crate) struct SoundBank<'aud> {
+    audio: &'aud RaylibAudio,
+    entries: HashMap<String, SoundEntry<'aud>>,
+    settings: SoundSettings,
+}
+
+impl<'aud> SoundBank<'aud> {
+    pub(crate) fn new(audio: &'aud RaylibAudio, settings: SoundSettings) -> SoundBank<'aud> {
+        SoundBank {
+            audio,
+            entries: HashMap::new(),
+            settings,
+        }
+    }
+
+    /// Loads `filename` under `name`.
+    ///
+    /// * `polyphony` - How many copies of this sound can play at once. When
+    ///                 all are busy the oldest one is cut off.
+    pub(crate) fn load(
+        &mut self,
+        name: &str,
+        filename: &str,
+        category: SoundCategory,
+        polyphony: usize,
+        variation: Variation,
+    ) -> Result<(), String> {
+        let mut voices = Vec::new();
+        for _ in 0..polyphony.max(1) {
+            voices.push(self.audio.new_sound(filename)?);
+        }
+        self.insert(name, category, voices, variation);
+        Ok(())
+    }
+
+    /// Renders a synthesized sound and loads it under `name`, same as `load`.
+    pub(crate) fn load_synth(
+        &mut self,
+        name: &str,
+        params: &SynthParams,
+        category: SoundCategory,
+        polyphony: usize,
+        variation: Variation,
+    ) -> Result<(), String> {
+        let sample_rate = 44100;
+        let samples = render(params, sample_rate);
+        self.load_samples(name, &samples, sample_rate, category, polyphony, variation)
+    }
+
+    /// Loads `filename` through an effect chain, e.g. to get a muffled or
+    /// echoing copy of an existing sound without another file on disk.
+    /// Silence at either end of the file is cut first so the sound plays
+    /// as soon as it's triggered.
+    pub(crate) fn load_processed(
+        &mut self,
+        name: &str,
+        filename: &str,
+        chain: &EffectChain,
+        category: SoundCategory,
+        polyphony: usize,
+        variation: Variation,
+    ) -> Result<(), String> {
+        let audio = trim_silence(&WavAudio::new(filename).map_err(|e| e.to_string())?, TRIM_DB);
+        let samples = chain.process(&audio.to_f32(), audio.sample_rate());
+        self.load_samples(name, &samples, audio.sample_rate(), category, polyphony, variation)
+    }
+
+    /// Loads -1 to 1 mono samples as a sound.
+    pub(crate) fn load_samples(
+        &mut self,
+        name: &str,
+        samples: &[f32],
+        sample_rate: u32,
+        category: SoundCategory,
+        polyphony: usize,
+        variation: Variation,
+    ) -> Result<(), String> {
+        let bytes = to_wav_bytes(samples, sample_rate).map_err(|e| e.to_string())?;
+        let wave = self.audio.new_wave_from_memory(".wav", &bytes)?;
+        let mut voices = Vec::new();
+        for _ in 0..polyphony.max(1) {
+            voices.push(self.audio.new_sound_from_wave(&wave)?);
+        }
+        self.insert(name, category, voices, variation);
+        Ok(())
+    }
+
+    fn insert(&mut self, name: &str, category: SoundCategory, voices: Vec<Sound<'aud>>, variation: Variation) {
+        self.entries.insert(
+            name.to_string(),
+            SoundEntry {
+                category,
+                voices,
+                next_voice: 0,
+                volume: 1.0,
+                variation,
+            },
+        );
+    }
+
+    /// Sets the volume of a single sound, on top of its category's volume.
+    pub(crate) fn set_volume(&mut self, name: &str, volume: f32) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.volume = volume.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Plays the sound called `name`, if it exists and isn't muted.
+    pub(crate) fn play(&mut self, name: &str) {
+        self.play_panned(name, 0.5, 1.0);
+    }
+
+    /// Plays `name` as if it came from `x` on a screen `width` pixels wide.
+    pub(crate) fn play_at(&mut self, name: &str, x: f32, width: f32) {
+        let (pan, volume) = spatialize(x, width);
+        self.play_panned(name, pan, volume);
+    }
+
+    /// Plays `name` with a raylib pan (0.5 is center) and an extra volume
+    /// multiplier.
+    pub(crate) fn play_panned(&mut self, name: &str, pan: f32, volume: f32) {
+        let Some(entry) = self.entries.get_mut(name) else {
+            return;
+        };
+        let volume = self.settings.category_volume(entry.category) * entry.volume * volume;
+        if volume <= 0.0 {
+            return;
+        }
+
+        // prefer an idle voice, otherwise steal the next one in line
+        let voice_count = entry.voices.len();
+        let idx = (0..voice_count)
+            .map(|i| (entry.next_voice + i) % voice_count)
+            .find(|i| !entry.voices[*i].is_playing())
+            .unwrap_or(entry.next_voice);
+        entry.next_voice = (idx + 1) % voice_count;
+
+        let mut rng = rand::thread_rng();
+        let pitch_offset = entry.variation.pitch * rng.gen_range(-1.0f32..=1.0);
+        let volume_offset = entry.variation.volume * rng.gen_range(-1.0f32..=1.0);
+
+        let voice = &mut entry.voices[idx];
+        voice.stop();
+        voice.set_pitch((1.0 + pitch_offset).max(0.01));
+        voice.set_volume((volume * (1.0 + volume_offset)).clamp(0.0, 1.0));
+        voice.set_pan(pan.clamp(0.0, 1.0));
+        voice.play();
+    }
+
+    pub(crate) fn settings(&self) -> &SoundSettings {
+        &self.settings
+    }
+
+    pub(crate) fn set_settings(&mut self, settings: SoundSettings) {
+        self.settings = settings;
+        if settings.muted {
+            self.stop_all();
+        }
+    }
+
+    pub(crate) fn toggle_mute(&mut self) {
+        let mut settings = self.settings;
+        settings.muted = !settings.muted;
+        self.set_settings(settings);
+    }
+
+    pub(crate) fn stop_all(&mut self) {
+        for entry in self.entries.values_mut() {
+            for voice in entry.voices.iter_mut() {
+                voice.stop();
+            }
+        }
+    }
+}
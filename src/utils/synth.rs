@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::io::Cursor;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Waveform {
+    Square,
+    Saw,
+    Sine,
+    Noise,
+}
+
+impl Waveform {
+    fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "saw" => Some(Waveform::Saw),
+            "sine" => Some(Waveform::Sine),
+            "noise" => Some(Waveform::Noise),
+            _ => None,
+        }
+    }
+}
+
+/// Everything needed to render one sfxr style sound effect.
+/// Times are in seconds, frequencies in Hz.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SynthParams {
+    pub wave: Waveform,
+    pub frequency: f32,
+    /// Pitch change in octaves per second, negative slides down.
+    pub slide: f32,
+    /// The slide stops once it gets this low, 0 lets it go all the way.
+    pub min_frequency: f32,
+    /// Fraction of each square wave period spent high.
+    pub duty: f32,
+    pub attack: f32,
+    pub sustain: f32,
+    /// Extra volume at the start of the sustain that fades out over it.
+    pub punch: f32,
+    pub decay: f32,
+    /// Bit depth to crush down to, 0 leaves it alone.
+    pub crush_bits: u32,
+    /// Only take a new sample every `crush_rate` samples, 1 leaves it alone.
+    pub crush_rate: u32,
+    pub volume: f32,
+    /// Seed for the noise, so a sound renders the same every time.
+    pub seed: u64,
+}
+
+impl Default for SynthParams {
+    fn default() -> Self {
+        SynthParams {
+            wave: Waveform::Square,
+            frequency: 440.0,
+            slide: 0.0,
+            min_frequency: 0.0,
+            duty: 0.5,
+            attack: 0.0,
+            sustain: 0.1,
+            punch: 0.0,
+            decay: 0.2,
+            crush_bits: 0,
+            crush_rate: 1,
+            volume: 0.5,
+            seed: 0,
+        }
+    }
+}
+
+impl SynthParams {
+    pub(crate) fn duration(&self) -> f32 {
+        self.attack + self.sustain + self.decay
+    }
+
+    /// Volume envelope at `t` seconds in.
+    fn envelope(&self, t: f32) -> f32 {
+        if t < self.attack {
+            return t / self.attack;
+        }
+        let t = t - self.attack;
+        if t < self.sustain {
+            return 1.0 + self.punch * (1.0 - t / self.sustain);
+        }
+        let t = t - self.sustain;
+        if t < self.decay {
+            return 1.0 - t / self.decay;
+        }
+        0.0
+    }
+
+    /// Sets the field called `key` from its text form.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let bad = || format!("bad value for {}: {}", key, value);
+        let float = || value.parse::<f32>().map_err(|_| bad());
+        match key {
+            "wave" => self.wave = Waveform::from_name(value).ok_or_else(bad)?,
+            "frequency" => self.frequency = float()?,
+            "slide" => self.slide = float()?,
+            "min_frequency" => self.min_frequency = float()?,
+            "duty" => self.duty = float()?,
+            "attack" => self.attack = float()?,
+            "sustain" => self.sustain = float()?,
+            "punch" => self.punch = float()?,
+            "decay" => self.decay = float()?,
+            "crush_bits" => self.crush_bits = value.parse().map_err(|_| bad())?,
+            "crush_rate" => self.crush_rate = value.parse().map_err(|_| bad())?,
+            "volume" => self.volume = float()?,
+            "seed" => self.seed = value.parse().map_err(|_| bad())?,
+            _ => return Err(format!("unknown synth parameter {}", key)),
+        }
+        Ok(())
+    }
+}
+
+/// Renders `params` into -1 to 1 mono samples.
+pub(crate) fn render(params: &SynthParams, sample_rate: u32) -> Vec<f32> {
+    let rate = sample_rate as f32;
+    let len = (params.duration() * rate).ceil() as usize;
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let mut out = Vec::with_capacity(len);
+
+    let mut phase = 0.0f32; // 0 to 1 through the current period
+    let mut noise = rng.gen_range(-1.0f32..=1.0);
+    let mut held = 0.0;
+    for i in 0..len {
+        let t = i as f32 / rate;
+        let freq = (params.frequency * (params.slide * t).exp2()).max(params.min_frequency);
+
+        let osc = match params.wave {
+            Waveform::Square => if phase < params.duty { 1.0 } else { -1.0 },
+            Waveform::Saw => 1.0 - 2.0 * phase,
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Noise => noise,
+        };
+        phase += freq / rate;
+        if phase >= 1.0 {
+            phase = phase.fract();
+            // sfxr noise holds one random value per period, so it still has a pitch
+            noise = rng.gen_range(-1.0f32..=1.0);
+        }
+
+        let mut sample = osc * params.envelope(t) * params.volume;
+        if params.crush_bits > 0 && params.crush_bits < 16 {
+            let steps = (1u32 << (params.crush_bits - 1)) as f32;
+            sample = (sample * steps).round() / steps;
+        }
+        if i % params.crush_rate.max(1) as usize == 0 {
+            held = sample;
+        }
+        out.push(held.clamp(-1.0, 1.0));
+    }
+    out
+}
+
+/// Encodes samples as a 16 bit mono WAV file in memory, which is what
+/// `RaylibAudio::new_wave_from_memory` wants.
+pub(crate) fn to_wav_bytes(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>, hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut bytes = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut bytes, spec)?;
+    for s in samples {
+        writer.write_sample((s * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(bytes.into_inner())
+}
+
+pub(crate) fn export_wav(filename: &str, samples: &[f32], sample_rate: u32) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::write(filename, to_wav_bytes(samples, sample_rate)?)?;
+    Ok(())
+}
+
+/// Reads sound definitions out of a level file. Each sound is a `[name]`
+/// line followed by `key = value` lines, anything left out keeps its default.
+/// Lines starting with `#` are ignored.
+pub(crate) fn parse_sounds(text: &str) -> Result<HashMap<String, SynthParams>, String> {
+    let mut sounds = HashMap::new();
+    let mut current: Option<(String, SynthParams)> = None;
+    for (num, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            if let Some((name, params)) = current.take() {
+                sounds.insert(name, params);
+            }
+            current = Some((name.trim().to_string(), SynthParams::default()));
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected `key = value`", num + 1));
+        };
+        let Some((_, params)) = current.as_mut() else {
+            return Err(format!("line {}: parameter before any [sound]", num + 1));
+        };
+        params
+            .set(key.trim(), value.trim())
+            .map_err(|e| format!("line {}: {}", num + 1, e))?;
+    }
+    if let Some((name, params)) = current {
+        sounds.insert(name, params);
+    }
+    Ok(sounds)
+}
+
+pub(crate) fn load_sounds(filename: &str) -> Result<HashMap<String, SynthParams>, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(filename)?;
+    Ok(parse_sounds(&text).map_err(|e| format!("{}: {}", filename, e))?)
+}
+
+/// Entry point for `luigi_flowey sfx <level file> <output dir>`, which
+/// writes every sound in the level file out as `<name>.wav`.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (input, out_dir) = match args {
+        [input, out_dir] => (input, out_dir),
+        _ => return Err("usage: luigi_flowey sfx <level file> <output dir>".into()),
+    };
+
+    let sounds = load_sounds(input)?;
+    std::fs::create_dir_all(out_dir)?;
+    for (name, params) in sounds.iter() {
+        let filename = format!("{}/{}.wav", out_dir, name);
+        export_wav(&filename, &render(params, 44100), 44100)?;
+        println!("{} -> {}", name, filename);
+    }
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use raylib::prelude::*;
+
+const ELLIPSIS: &str = "...";
+
+/// How to fit a paragraph into a box.
+pub(crate) struct WrapConfig {
+    pub max_width: f32,
+    /// Lines past this are dropped.
+    pub max_lines: Option<usize>,
+    /// End the last line with "..." when lines were dropped.
+    pub ellipsis: bool,
+}
+
+/// A paragraph broken into lines. `size` is the bounding box of the lines
+/// as `draw_text_anchored` stacks them with `line_spacing` 1.
+#[derive(Clone, Debug)]
+pub(crate) struct WrappedText {
+    pub lines: Vec<String>,
+    pub size: Vector2,
+    pub truncated: bool,
+}
+
+impl WrappedText {
+    /// The lines in the form `draw_text_anchored` takes them.
+    pub(crate) fn line_refs(&self) -> Vec<&str> {
+        self.lines.iter().map(|l| l.as_str()).collect()
+    }
+}
+
+/// Width of `text` by `measure`, skipping it for empty text.
+fn width_of(measure: &impl Fn(&str) -> f32, text: &str) -> f32 {
+    if text.is_empty() {
+        return 0.0;
+    }
+    measure(text)
+}
+
+/// Splits a word that's wider than the box into pieces that fit, at least
+/// one character each.
+fn break_word(measure: &impl Fn(&str) -> f32, word: &str, max_width: f32) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    for ch in word.chars() {
+        piece.push(ch);
+        if piece.chars().count() > 1 && width_of(measure, &piece) > max_width {
+            piece.pop();
+            pieces.push(std::mem::replace(&mut piece, ch.to_string()));
+        }
+    }
+    pieces.push(piece);
+    return pieces;
+}
+
+/// Shortens `line` until it fits with "..." on the end.
+fn ellipsize(measure: &impl Fn(&str) -> f32, line: &str, max_width: f32) -> String {
+    let mut kept = line.trim_end().to_string();
+    loop {
+        let candidate = format!("{}{}", kept, ELLIPSIS);
+        if kept.is_empty() || width_of(measure, &candidate) <= max_width {
+            return candidate;
+        }
+        kept.pop();
+        kept = kept.trim_end().to_string();
+    }
+}
+
+/// Word wraps `text` to `config.max_width`. Newlines in `text` always start
+/// a new line, and words too long for a line of their own are broken up.
+/// Whitespace between words is kept as it is, except where a line breaks.
+pub(crate) fn wrap_text(font: &Font, text: &str, font_size: f32, spacing: f32, config: &WrapConfig) -> WrappedText {
+    let measure = |line: &str| font.measure_text(line, font_size, spacing).x;
+    return wrap_measured(text, font_size, spacing, config, measure);
+}
+
+/// The font free part of `wrap_text`: `measure` gives the width of a piece
+/// of text as it would be drawn at `font_size`.
+pub(crate) fn wrap_measured(
+    text: &str,
+    font_size: f32,
+    spacing: f32,
+    config: &WrapConfig,
+    measure: impl Fn(&str) -> f32,
+) -> WrappedText {
+    let mut lines: Vec<String> = Vec::new();
+    // no text is no lines, rather than one empty one
+    let paragraphs = text.split('\n').take_while(|_| !text.is_empty());
+    for paragraph in paragraphs {
+        let mut line = String::new();
+        let mut rest = paragraph;
+        loop {
+            let word_start = rest.len() - rest.trim_start().len();
+            let gap = &rest[..word_start];
+            rest = &rest[word_start..];
+            if rest.is_empty() {
+                break;
+            }
+            let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let word = &rest[..word_end];
+            rest = &rest[word_end..];
+
+            let candidate = format!("{}{}{}", line, gap, word);
+            if width_of(&measure, &candidate) <= config.max_width {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            let mut pieces = break_word(&measure, word, config.max_width);
+            line = pieces.pop().unwrap_or_default();
+            lines.extend(pieces);
+        }
+        lines.push(line);
+    }
+
+    let mut truncated = false;
+    if let Some(max_lines) = config.max_lines {
+        if lines.len() > max_lines {
+            lines.truncate(max_lines);
+            truncated = true;
+            if config.ellipsis {
+                if let Some(last) = lines.last_mut() {
+                    *last = ellipsize(&measure, last, config.max_width);
+                }
+            }
+        }
+    }
+
+    let width = lines.iter().map(|l| width_of(&measure, l)).fold(0.0, f32::max);
+    let height = if lines.is_empty() {
+        0.0
+    } else {
+        lines.len() as f32 * font_size + spacing * (lines.len() - 1) as f32
+    };
+    WrappedText {
+        lines,
+        size: Vector2::new(width, height),
+        truncated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every character 10 wide, so `max_width` 100 fits 10 of them.
+    fn wrap(text: &str, max_width: f32, max_lines: Option<usize>, ellipsis: bool) -> WrappedText {
+        let config = WrapConfig {
+            max_width,
+            max_lines,
+            ellipsis,
+        };
+        return wrap_measured(text, 20.0, 2.0, &config, |s| s.chars().count() as f32 * 10.0);
+    }
+
+    fn lines(text: &str, max_width: f32) -> Vec<String> {
+        return wrap(text, max_width, None, false).lines;
+    }
+
+    #[test]
+    fn words_wrap_at_the_width() {
+        assert_eq!(lines("the quick brown fox", 100.0), vec!["the quick", "brown fox"]);
+        assert_eq!(lines("the quick brown fox", 1000.0), vec!["the quick brown fox"]);
+        // spacing between words stays, except at a break
+        assert_eq!(lines("a  b   cdefghijk", 100.0), vec!["a  b", "cdefghijk"]);
+    }
+
+    #[test]
+    fn newlines_always_break() {
+        assert_eq!(lines("ab\ncd", 1000.0), vec!["ab", "cd"]);
+        assert_eq!(lines("a\n\nb\n", 1000.0), vec!["a", "", "b", ""]);
+        assert!(lines("", 1000.0).is_empty());
+    }
+
+    #[test]
+    fn long_words_are_broken_up() {
+        assert_eq!(lines("abcdefghijklmnopqrstuvwxy", 100.0), vec!["abcdefghij", "klmnopqrst", "uvwxy"]);
+        assert_eq!(lines("hi abcdefghijkl ok", 100.0), vec!["hi", "abcdefghij", "kl ok"]);
+        // at least a character a line, even when one doesn't fit
+        assert_eq!(lines("abc", 5.0), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn extra_lines_are_dropped_with_an_ellipsis() {
+        let wrapped = wrap("one two three four", 80.0, Some(2), true);
+        assert_eq!(wrapped.lines, vec!["one two", "three..."]);
+        assert!(wrapped.truncated);
+
+        // the last line is shortened to make room
+        assert_eq!(wrap("one two three four", 80.0, Some(1), true).lines, vec!["one t..."]);
+
+        let wrapped = wrap("one two three four", 80.0, Some(2), false);
+        assert_eq!(wrapped.lines, vec!["one two", "three"]);
+        assert!(wrapped.truncated);
+
+        let wrapped = wrap("one two three four", 80.0, Some(3), true);
+        assert_eq!(wrapped.lines, vec!["one two", "three", "four"]);
+        assert!(!wrapped.truncated);
+    }
+
+    #[test]
+    fn size_covers_the_widest_line() {
+        let wrapped = wrap("the quick brown fox\njumps", 100.0, None, false);
+        assert_eq!(wrapped.lines.len(), 3);
+        // three 20 high lines with 2 between them
+        assert_eq!((wrapped.size.x, wrapped.size.y), (90.0, 64.0));
+        let empty = wrap("", 100.0, None, false);
+        assert_eq!((empty.size.x, empty.size.y), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,394 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::time::UNIX_EPOCH;
+use raylib::prelude::*;
+use crate::utils::audio::WavAudio;
+use crate::utils::render_graph::bar_span;
+
+/// Samples summarized by each value of the finest level.
+const BASE_BUCKET: usize = 64;
+const CACHE_MAGIC: &[u8; 4] = b"LFWF";
+const CACHE_VERSION: u32 = 1;
+/// Magic, version, modified time, sample rate, length and peak count.
+const CACHE_HEADER: u64 = 36;
+/// min, max and rms as f32s.
+const CACHE_PEAK: u64 = 12;
+
+/// Summary of a run of samples, all in the -1 to 1 range.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Peak {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+impl Peak {
+    /// Combines summaries of `a_len` and `b_len` samples.
+    fn merge(a: Peak, a_len: usize, b: Peak, b_len: usize) -> Peak {
+        let total = (a_len + b_len).max(1) as f32;
+        let square = a.rms * a.rms * a_len as f32 + b.rms * b.rms * b_len as f32;
+        Peak {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+            rms: (square / total).sqrt(),
+        }
+    }
+}
+
+/// One zoom level, each peak covering `bucket` samples.
+struct Level {
+    bucket: usize,
+    peaks: Vec<Peak>,
+}
+
+/// Min/max/RMS summaries of a track at every zoom level from
+/// `BASE_BUCKET` samples per value down to the whole track in one, each
+/// level half the size of the one before.
+pub(crate) struct Thumbnail {
+    sample_rate: u32,
+    len: usize,
+    levels: Vec<Level>,
+}
+
+impl Thumbnail {
+    pub(crate) fn build(audio: &WavAudio) -> Thumbnail {
+        let scale = audio.full_scale() as f32;
+        let base = audio
+            .samples()
+            .chunks(BASE_BUCKET)
+            .map(|chunk| {
+                let mut peak = Peak {
+                    min: f32::MAX,
+                    max: f32::MIN,
+                    rms: 0.0,
+                };
+                let mut square = 0.0;
+                for s in chunk {
+                    let v = *s as f32 / scale;
+                    peak.min = peak.min.min(v);
+                    peak.max = peak.max.max(v);
+                    square += v * v;
+                }
+                peak.rms = (square / chunk.len() as f32).sqrt();
+                peak
+            })
+            .collect();
+        Thumbnail::from_base(audio.sample_rate(), audio.samples().len(), base)
+    }
+
+    /// Builds the coarser levels on top of the finest one.
+    fn from_base(sample_rate: u32, len: usize, base: Vec<Peak>) -> Thumbnail {
+        let mut levels = vec![Level {
+            bucket: BASE_BUCKET,
+            peaks: base,
+        }];
+        while levels[levels.len() - 1].peaks.len() > 1 {
+            let last = &levels[levels.len() - 1];
+            let bucket = last.bucket;
+            let peaks = last
+                .peaks
+                .chunks(2)
+                .enumerate()
+                .map(|(i, pair)| match pair {
+                    [a, b] => {
+                        // the last bucket of the track can be short
+                        let b_len = bucket.min(len.saturating_sub((i * 2 + 1) * bucket));
+                        Peak::merge(*a, bucket, *b, b_len)
+                    }
+                    _ => pair[0],
+                })
+                .collect();
+            levels.push(Level {
+                bucket: bucket * 2,
+                peaks,
+            });
+        }
+        Thumbnail {
+            sample_rate,
+            len,
+            levels,
+        }
+    }
+
+    pub(crate) fn duration_secs(&self) -> f64 {
+        self.len as f64 / self.sample_rate as f64
+    }
+
+    /// One peak per pixel for `start..end` seconds drawn `pixels` wide,
+    /// read from the coarsest level that still has a value per pixel.
+    pub(crate) fn summarize(&self, start: f64, end: f64, pixels: usize) -> Vec<Peak> {
+        if pixels == 0 || end <= start {
+            return Vec::new();
+        }
+        let rate = self.sample_rate as f64;
+        let per_pixel = (end - start) * rate / pixels as f64;
+        let level = self.level(per_pixel);
+
+        (0..pixels)
+            .map(|p| {
+                let from = start * rate + p as f64 * per_pixel;
+                let to = from + per_pixel;
+                let first = (from / level.bucket as f64).floor().max(0.0) as usize;
+                // always at least one bucket, so zooming in past the finest
+                // level stretches it instead of leaving holes
+                let last = ((to / level.bucket as f64).ceil() as usize).max(first + 1);
+                let mut peaks = level.peaks.iter().take(last).skip(first);
+                let Some(mut total) = peaks.next().cloned() else {
+                    return Peak::default();
+                };
+                for (count, peak) in (1..).zip(peaks) {
+                    total = Peak::merge(total, count, *peak, 1);
+                }
+                total
+            })
+            .collect()
+    }
+
+    /// The coarsest level with at least one value per `per_pixel` samples.
+    fn level(&self, per_pixel: f64) -> &Level {
+        return self
+            .levels
+            .iter()
+            .rev()
+            .find(|l| l.bucket as f64 <= per_pixel)
+            .unwrap_or(&self.levels[0]);
+    }
+
+    /// Loads the thumbnail for `filename` from its `.peaks` cache, or
+    /// decodes the file, builds it and writes the cache if that's missing or
+    /// older than the file.
+    pub(crate) fn load_cached(filename: &str) -> Result<Thumbnail, Box<dyn std::error::Error>> {
+        let cache = format!("{}.peaks", filename);
+        let modified = std::fs::metadata(filename)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+
+        if let Ok(thumbnail) = Thumbnail::read_cache(&cache, modified) {
+            return Ok(thumbnail);
+        }
+        let thumbnail = Thumbnail::build(&WavAudio::new(filename)?);
+        if let Err(e) = thumbnail.write_cache(&cache, modified) {
+            eprintln!("couldn't write {}: {}", cache, e);
+        }
+        Ok(thumbnail)
+    }
+
+    /// Only the finest level is stored, the rest are quick to rebuild.
+    fn write_cache(&self, filename: &str, source_modified: u64) -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(filename)?);
+        out.write_all(CACHE_MAGIC)?;
+        out.write_all(&CACHE_VERSION.to_le_bytes())?;
+        out.write_all(&source_modified.to_le_bytes())?;
+        out.write_all(&self.sample_rate.to_le_bytes())?;
+        out.write_all(&(self.len as u64).to_le_bytes())?;
+        let base = &self.levels[0].peaks;
+        out.write_all(&(base.len() as u64).to_le_bytes())?;
+        for peak in base.iter() {
+            for v in [peak.min, peak.max, peak.rms] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+        out.flush()
+    }
+
+    fn read_cache(filename: &str, source_modified: u64) -> Result<Thumbnail, Box<dyn std::error::Error>> {
+        let file = File::open(filename)?;
+        let file_len = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut word = [0u8; 4];
+        let mut long = [0u8; 8];
+        input.read_exact(&mut word)?;
+        if &word != CACHE_MAGIC {
+            return Err("not a waveform cache".into());
+        }
+        input.read_exact(&mut word)?;
+        if u32::from_le_bytes(word) != CACHE_VERSION {
+            return Err("old waveform cache".into());
+        }
+        input.read_exact(&mut long)?;
+        if u64::from_le_bytes(long) != source_modified {
+            return Err("waveform cache is stale".into());
+        }
+        input.read_exact(&mut word)?;
+        let sample_rate = u32::from_le_bytes(word);
+        input.read_exact(&mut long)?;
+        let len = u64::from_le_bytes(long) as usize;
+        input.read_exact(&mut long)?;
+        let count = u64::from_le_bytes(long);
+        // both come from the file, so check them before allocating anything
+        let expected_len = count.checked_mul(CACHE_PEAK).and_then(|n| n.checked_add(CACHE_HEADER));
+        if expected_len != Some(file_len) || count as usize != len.div_ceil(BASE_BUCKET) {
+            return Err("waveform cache is corrupt".into());
+        }
+        let count = count as usize;
+
+        let mut base = Vec::with_capacity(count);
+        let mut read_f32 = || -> std::io::Result<f32> {
+            input.read_exact(&mut word)?;
+            Ok(f32::from_le_bytes(word))
+        };
+        for _ in 0..count {
+            base.push(Peak {
+                min: read_f32()?,
+                max: read_f32()?,
+                rms: read_f32()?,
+            });
+        }
+        Ok(Thumbnail::from_base(sample_rate, len, base))
+    }
+}
+
+/// Draws one bar per peak across `bounds`, the min to max range in `color`
+/// with the RMS over it in `rms_color`, centered vertically.
+pub(crate) fn draw_thumbnail<T: RaylibDraw>(
+    d: &mut T,
+    peaks: &[Peak],
+    bounds: Rectangle,
+    gap: i32,
+    color: Color,
+    rms_color: Color,
+) {
+    let left = bounds.x as i32;
+    let right = (bounds.x + bounds.width) as i32;
+    let mid = bounds.y + bounds.height / 2.0;
+    let half = bounds.height / 2.0;
+    let bars = peaks.len() as i32;
+    for (i, peak) in peaks.iter().enumerate() {
+        let (x, width) = bar_span(i as i32, left, right, bars, gap);
+        let top = mid - peak.max.clamp(-1.0, 1.0) * half;
+        let bottom = mid - peak.min.clamp(-1.0, 1.0) * half;
+        d.draw_rectangle(x, top as i32, width, ((bottom - top) as i32).max(1), color);
+        let rms = peak.rms.clamp(0.0, 1.0) * half;
+        d.draw_rectangle(x, (mid - rms) as i32, width, ((rms * 2.0) as i32).max(1), rms_color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mono 8kHz WAV whose samples rise by one per frame from -10000.
+    fn write_wav(name: &str, frames: usize) -> String {
+        let path = std::env::temp_dir().join(format!("luigi_flowey_{}_{}.wav", name, std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..frames {
+            writer.write_sample((i as i32 - 10000) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn remove(path: &str) {
+        std::fs::remove_file(path).ok();
+        std::fs::remove_file(format!("{}.peaks", path)).ok();
+    }
+
+    #[test]
+    fn merge_weights_rms_by_length() {
+        let loud = Peak {
+            min: -1.0,
+            max: 1.0,
+            rms: 1.0,
+        };
+        let quiet = Peak {
+            min: -0.25,
+            max: 0.5,
+            rms: 0.0,
+        };
+        let merged = Peak::merge(loud, 1, quiet, 3);
+        assert_eq!((merged.min, merged.max), (-1.0, 1.0));
+        assert!((merged.rms - 0.5).abs() < 1e-6, "{}", merged.rms);
+        assert_eq!(Peak::merge(loud, 5, quiet, 0), loud);
+    }
+
+    #[test]
+    fn levels_halve_down_to_one_peak() {
+        let audio = WavAudio::from_samples(vec![0; 1000], 8000, 16);
+        let thumbnail = Thumbnail::build(&audio);
+        let sizes: Vec<(usize, usize)> = thumbnail.levels.iter().map(|l| (l.bucket, l.peaks.len())).collect();
+        assert_eq!(sizes, vec![(64, 16), (128, 8), (256, 4), (512, 2), (1024, 1)]);
+    }
+
+    #[test]
+    fn summarize_picks_the_coarsest_level_that_fits() {
+        let audio = WavAudio::from_samples(vec![0; 8000], 8000, 16);
+        let thumbnail = Thumbnail::build(&audio);
+        assert_eq!(thumbnail.level(10.0).bucket, 64);
+        assert_eq!(thumbnail.level(64.0).bucket, 64);
+        assert_eq!(thumbnail.level(200.0).bucket, 128);
+        assert_eq!(thumbnail.level(256.0).bucket, 256);
+        assert_eq!(thumbnail.level(1e9).bucket, 8192);
+    }
+
+    #[test]
+    fn summarize_covers_the_range() {
+        // a ramp, so each pixel's max is the last sample it covers
+        let samples: Vec<i32> = (0..8192).map(|i| i * 4).collect();
+        let thumbnail = Thumbnail::build(&WavAudio::from_samples(samples, 8192, 16));
+        let peaks = thumbnail.summarize(0.0, 1.0, 4);
+        assert_eq!(peaks.len(), 4);
+        for (p, peak) in peaks.iter().enumerate() {
+            let last = ((p + 1) * 2048 - 1) * 4;
+            assert_eq!(peak.max, last as f32 / 32768.0, "pixel {}", p);
+        }
+        // zoomed in past the finest level every pixel still has a value
+        let zoomed = thumbnail.summarize(0.0, 0.01, 100);
+        assert_eq!(zoomed.len(), 100);
+        assert!(zoomed.iter().all(|p| p.max >= thumbnail.levels[0].peaks[0].max));
+        assert!(thumbnail.summarize(1.0, 1.0, 10).is_empty());
+        assert!(thumbnail.summarize(0.0, 1.0, 0).is_empty());
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let path = write_wav("thumbnail_cache", 5000);
+        std::fs::remove_file(format!("{}.peaks", path)).ok();
+        let built = Thumbnail::load_cached(&path).unwrap();
+        let cache = format!("{}.peaks", path);
+        let count = 5000usize.div_ceil(BASE_BUCKET) as u64;
+        assert_eq!(std::fs::metadata(&cache).unwrap().len(), CACHE_HEADER + count * CACHE_PEAK);
+
+        let loaded = Thumbnail::load_cached(&path).unwrap();
+        assert_eq!((loaded.sample_rate, loaded.len), (8000, 5000));
+        assert_eq!(loaded.levels.len(), built.levels.len());
+        for (a, b) in loaded.levels.iter().zip(built.levels.iter()) {
+            assert_eq!(a.peaks, b.peaks);
+        }
+        remove(&path);
+    }
+
+    #[test]
+    fn stale_or_truncated_caches_are_rejected() {
+        let path = write_wav("thumbnail_stale", 3000);
+        let cache = format!("{}.peaks", path);
+        let thumbnail = Thumbnail::build(&WavAudio::new(&path).unwrap());
+        thumbnail.write_cache(&cache, 100).unwrap();
+        assert!(Thumbnail::read_cache(&cache, 100).is_ok());
+
+        let error = Thumbnail::read_cache(&cache, 101).err().unwrap();
+        assert_eq!(error.to_string(), "waveform cache is stale");
+
+        let bytes = std::fs::read(&cache).unwrap();
+        std::fs::write(&cache, &bytes[..bytes.len() - 4]).unwrap();
+        let error = Thumbnail::read_cache(&cache, 100).err().unwrap();
+        assert_eq!(error.to_string(), "waveform cache is corrupt");
+
+        std::fs::write(&cache, &bytes[..10]).unwrap();
+        assert!(Thumbnail::read_cache(&cache, 100).is_err());
+
+        // load_cached falls back to decoding and rewrites the cache
+        std::fs::write(&cache, &bytes[..bytes.len() - 4]).unwrap();
+        let loaded = Thumbnail::load_cached(&path).unwrap();
+        assert_eq!(loaded.levels[0].peaks, thumbnail.levels[0].peaks);
+        assert_eq!(std::fs::metadata(&cache).unwrap().len(), bytes.len() as u64);
+        remove(&path);
+    }
+}
@@ -0,0 +1,699 @@
+/// Note value meaning "release the note" rather than a pitch.
+pub(crate) const KEY_OFF: u8 = 97;
+
+/// One channel of one row.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Cell {
+    /// 1 to 96 is C-0 to B-7, `KEY_OFF` releases, 0 is empty.
+    pub note: u8,
+    /// 1 based, 0 keeps whatever the channel had.
+    pub instrument: u8,
+    /// XM volume column, 0 is empty.
+    pub volume: u8,
+    pub effect: u8,
+    pub param: u8,
+}
+
+pub(crate) struct Pattern {
+    pub rows: usize,
+    /// `rows * channels` cells, row by row.
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LoopKind {
+    None,
+    Forward,
+    PingPong,
+}
+
+pub(crate) struct Sample {
+    pub data: Vec<f32>,
+    pub loop_start: usize,
+    pub loop_len: usize,
+    pub loop_kind: LoopKind,
+    /// 0 to 64.
+    pub volume: u8,
+    /// In 1/128ths of a semitone.
+    pub finetune: i8,
+    /// Semitones added to every note played with this sample.
+    pub relative_note: i8,
+}
+
+/// A volume envelope, `points` are (tick, volume 0 to 64).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Envelope {
+    pub points: Vec<(u16, u8)>,
+    pub sustain: Option<usize>,
+    pub loop_range: Option<(usize, usize)>,
+}
+
+impl Envelope {
+    /// Volume from 0 to 1 at `tick` ticks after the note started.
+    pub(crate) fn value_at(&self, tick: u16) -> f32 {
+        let Some(first) = self.points.first() else {
+            return 1.0;
+        };
+        if tick <= first.0 {
+            return first.1 as f32 / 64.0;
+        }
+        for pair in self.points.windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            if tick < x1 {
+                let t = (tick - x0) as f32 / (x1 - x0).max(1) as f32;
+                return (y0 as f32 + (y1 as f32 - y0 as f32) * t) / 64.0;
+            }
+        }
+        self.points[self.points.len() - 1].1 as f32 / 64.0
+    }
+
+    pub(crate) fn tick_of(&self, point: usize) -> Option<u16> {
+        self.points.get(point).map(|p| p.0)
+    }
+}
+
+pub(crate) struct Instrument {
+    /// Which of `samples` each of the 96 notes plays.
+    pub sample_map: [u8; 96],
+    pub samples: Vec<Sample>,
+    pub envelope: Option<Envelope>,
+    /// How much the volume drops per tick after key off, out of 65536.
+    pub fadeout: u16,
+}
+
+impl Instrument {
+    /// Index into `samples` of what `note` (1 to 96) plays.
+    pub(crate) fn sample_index(&self, note: u8) -> Option<usize> {
+        let idx = *self.sample_map.get(note.saturating_sub(1) as usize)? as usize;
+        Some(idx).filter(|i| *i < self.samples.len())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Format {
+    /// ProTracker, pitches are Amiga periods.
+    Mod,
+    /// FastTracker 2, `linear` picks linear or Amiga frequency tables.
+    Xm { linear: bool },
+}
+
+/// A tracker song, loaded from a MOD or XM file.
+pub(crate) struct Module {
+    pub name: String,
+    pub format: Format,
+    pub channels: usize,
+    pub orders: Vec<u8>,
+    pub restart: usize,
+    pub patterns: Vec<Pattern>,
+    pub instruments: Vec<Instrument>,
+    pub speed: u8,
+    pub tempo: u8,
+}
+
+impl Module {
+    pub(crate) fn load(filename: &str) -> Result<Module, Box<dyn std::error::Error>> {
+        let bytes = std::fs::read(filename)?;
+        if bytes.starts_with(b"Extended Module: ") {
+            return parse_xm(&bytes);
+        }
+        parse_mod(&bytes)
+    }
+
+    pub(crate) fn cell(&self, pattern: usize, row: usize, channel: usize) -> Cell {
+        self.patterns
+            .get(pattern)
+            .and_then(|p| p.cells.get(row * self.channels + channel))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Bounds-checked reads, so a truncated file is an error and not a panic.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], pos: usize) -> Reader<'a> {
+        Reader { bytes, pos }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let slice = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| format!("file ends early at byte {}", self.pos))?;
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16_be(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u16_le(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32_le(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+fn text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim_end().to_string()
+}
+
+/// Amiga periods for C-1 to B-3, the range ProTracker can play.
+const MOD_PERIODS: [u16; 36] = [
+    856, 808, 762, 720, 678, 640, 604, 570, 538, 508, 480, 453, 428, 404, 381, 360, 339, 320, 302, 285, 269, 254, 240,
+    226, 214, 202, 190, 180, 170, 160, 151, 143, 135, 127, 120, 113,
+];
+
+/// Turns a MOD period into the same note numbers XM uses, so both formats
+/// play through the same code. ProTracker's C-2 is XM's C-4.
+fn mod_period_to_note(period: u16) -> u8 {
+    if period == 0 {
+        return 0;
+    }
+    let nearest = MOD_PERIODS
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| (**p as i32 - period as i32).abs())
+        .map_or(0, |(i, _)| i);
+    (nearest + 3 * 12 + 1) as u8
+}
+
+fn parse_mod(bytes: &[u8]) -> Result<Module, Box<dyn std::error::Error>> {
+    let tag = bytes.get(1080..1084).ok_or("not a MOD or XM file")?;
+    let channels = match tag {
+        b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => 4,
+        b"6CHN" => 6,
+        b"8CHN" | b"FLT8" | b"OCTA" => 8,
+        [a, b, b'C', b'H'] | [a, b, b'C', b'N'] if a.is_ascii_digit() && b.is_ascii_digit() => {
+            ((a - b'0') * 10 + (b - b'0')) as usize
+        }
+        _ => return Err("not a MOD or XM file (old 15 sample MODs aren't supported)".into()),
+    };
+    if channels == 0 || channels > 64 {
+        return Err(format!("MOD has {} channels", channels).into());
+    }
+
+    let mut r = Reader::new(bytes, 0);
+    let name = text(r.take(20)?);
+    let mut headers = Vec::new();
+    for _ in 0..31 {
+        r.take(22)?;
+        let len = r.u16_be()? as usize * 2;
+        // finetune is a signed nibble of 1/8 semitones, kept as 1/128ths like XM
+        let finetune = ((r.u8()? & 0x0F) << 4) as i8;
+        let volume = r.u8()?.min(64);
+        let loop_start = r.u16_be()? as usize * 2;
+        let loop_len = r.u16_be()? as usize * 2;
+        headers.push((len, finetune, volume, loop_start, loop_len));
+    }
+    let song_length = (r.u8()? as usize).clamp(1, 128);
+    let restart = r.u8()? as usize;
+    let all_orders = r.take(128)?;
+    r.take(4)?;
+    let orders = all_orders[..song_length].to_vec();
+    let pattern_count = all_orders.iter().cloned().max().unwrap_or(0) as usize + 1;
+
+    let mut patterns = Vec::new();
+    for _ in 0..pattern_count {
+        let mut cells = Vec::with_capacity(64 * channels);
+        for _ in 0..64 * channels {
+            let b = r.take(4)?;
+            let period = ((b[0] as u16 & 0x0F) << 8) | b[1] as u16;
+            cells.push(Cell {
+                note: mod_period_to_note(period),
+                instrument: (b[0] & 0xF0) | (b[2] >> 4),
+                volume: 0,
+                effect: b[2] & 0x0F,
+                param: b[3],
+            });
+        }
+        patterns.push(Pattern { rows: 64, cells });
+    }
+
+    let mut instruments = Vec::new();
+    for (len, finetune, volume, loop_start, loop_len) in headers {
+        // some MODs are cut short, play what's there
+        let available = bytes.len().saturating_sub(r.pos).min(len);
+        let data = r.take(available)?.iter().map(|b| *b as i8 as f32 / 128.0).collect::<Vec<_>>();
+        let looped = loop_len > 2 && loop_start + loop_len <= data.len();
+        instruments.push(Instrument {
+            sample_map: [0; 96],
+            samples: vec![Sample {
+                data,
+                loop_start: if looped { loop_start } else { 0 },
+                loop_len: if looped { loop_len } else { 0 },
+                loop_kind: if looped { LoopKind::Forward } else { LoopKind::None },
+                volume,
+                finetune,
+                relative_note: 0,
+            }],
+            envelope: None,
+            fadeout: 0,
+        });
+    }
+
+    Ok(Module {
+        name,
+        format: Format::Mod,
+        channels,
+        orders,
+        restart: if restart < song_length { restart } else { 0 },
+        patterns,
+        instruments,
+        speed: 6,
+        tempo: 125,
+    })
+}
+
+fn parse_xm(bytes: &[u8]) -> Result<Module, Box<dyn std::error::Error>> {
+    let mut r = Reader::new(bytes, 17);
+    let name = text(r.take(20)?);
+    r.take(1 + 20 + 2)?;
+    let header_start = r.pos;
+    let header_size = r.u32_le()? as usize;
+    let song_length = r.u16_le()? as usize;
+    let restart = r.u16_le()? as usize;
+    let channels = r.u16_le()? as usize;
+    let pattern_count = r.u16_le()? as usize;
+    let instrument_count = r.u16_le()? as usize;
+    let flags = r.u16_le()?;
+    let speed = r.u16_le()?.clamp(1, 255) as u8;
+    let tempo = r.u16_le()?.clamp(32, 255) as u8;
+    let orders = r.take(256)?[..song_length.min(256)].to_vec();
+    if channels == 0 || channels > 64 {
+        return Err(format!("XM has {} channels", channels).into());
+    }
+    r.pos = header_start + header_size;
+
+    let mut patterns = Vec::new();
+    for _ in 0..pattern_count {
+        let start = r.pos;
+        let header_len = r.u32_le()? as usize;
+        r.u8()?;
+        let rows = r.u16_le()? as usize;
+        let packed_size = r.u16_le()? as usize;
+        r.pos = start + header_len;
+        let mut cells = vec![Cell::default(); rows * channels];
+        if packed_size > 0 {
+            let mut p = Reader::new(r.take(packed_size)?, 0);
+            for cell in cells.iter_mut() {
+                let first = p.u8()?;
+                // the top bit says which of the five fields follow
+                let mask = if first & 0x80 != 0 { first } else { 0x1F };
+                if first & 0x80 == 0 {
+                    p.pos -= 1;
+                }
+                if mask & 0x01 != 0 {
+                    cell.note = p.u8()?;
+                }
+                if mask & 0x02 != 0 {
+                    cell.instrument = p.u8()?;
+                }
+                if mask & 0x04 != 0 {
+                    cell.volume = p.u8()?;
+                }
+                if mask & 0x08 != 0 {
+                    cell.effect = p.u8()?;
+                }
+                if mask & 0x10 != 0 {
+                    cell.param = p.u8()?;
+                }
+            }
+        }
+        patterns.push(Pattern { rows, cells });
+    }
+
+    let mut instruments = Vec::new();
+    for _ in 0..instrument_count {
+        let start = r.pos;
+        let size = r.u32_le()? as usize;
+        r.take(22 + 1)?;
+        let sample_count = r.u16_le()? as usize;
+        if sample_count == 0 {
+            r.pos = start + size;
+            instruments.push(Instrument {
+                sample_map: [0; 96],
+                samples: Vec::new(),
+                envelope: None,
+                fadeout: 0,
+            });
+            continue;
+        }
+
+        r.u32_le()?;
+        let mut sample_map = [0u8; 96];
+        sample_map.copy_from_slice(r.take(96)?);
+        let mut points = Vec::new();
+        for _ in 0..12 {
+            let x = r.u16_le()?;
+            let y = r.u16_le()?.min(64) as u8;
+            points.push((x, y));
+        }
+        r.take(48)?; // panning envelope, everything is mono here
+        let point_count = (r.u8()? as usize).min(12);
+        r.u8()?;
+        let sustain = r.u8()? as usize;
+        let loop_start = r.u8()? as usize;
+        let loop_end = r.u8()? as usize;
+        r.take(3)?;
+        let envelope_flags = r.u8()?;
+        r.take(1 + 4)?;
+        let fadeout = r.u16_le()?;
+        r.pos = start + size;
+
+        let envelope = if envelope_flags & 1 != 0 && point_count > 0 {
+            points.truncate(point_count);
+            Some(Envelope {
+                points,
+                sustain: Some(sustain).filter(|_| envelope_flags & 2 != 0),
+                loop_range: Some((loop_start, loop_end)).filter(|_| envelope_flags & 4 != 0),
+            })
+        } else {
+            None
+        };
+
+        // all the sample headers come first, then all their data
+        let mut headers = Vec::new();
+        for _ in 0..sample_count {
+            let len = r.u32_le()? as usize;
+            let loop_start = r.u32_le()? as usize;
+            let loop_len = r.u32_le()? as usize;
+            let volume = r.u8()?.min(64);
+            let finetune = r.u8()? as i8;
+            let kind = r.u8()?;
+            r.u8()?;
+            let relative_note = r.u8()? as i8;
+            r.take(1 + 22)?;
+            headers.push((len, loop_start, loop_len, volume, finetune, kind, relative_note));
+        }
+        let mut samples = Vec::new();
+        for (len, loop_start, loop_len, volume, finetune, kind, relative_note) in headers {
+            let raw = r.take(len)?;
+            let sixteen_bit = kind & 0x10 != 0;
+            // stored as deltas from the previous sample
+            let data: Vec<f32> = if sixteen_bit {
+                let mut acc = 0i16;
+                raw.chunks_exact(2)
+                    .map(|b| {
+                        acc = acc.wrapping_add(i16::from_le_bytes([b[0], b[1]]));
+                        acc as f32 / 32768.0
+                    })
+                    .collect()
+            } else {
+                let mut acc = 0i8;
+                raw.iter()
+                    .map(|b| {
+                        acc = acc.wrapping_add(*b as i8);
+                        acc as f32 / 128.0
+                    })
+                    .collect()
+            };
+            let width = if sixteen_bit { 2 } else { 1 };
+            let (loop_start, loop_len) = (loop_start / width, loop_len / width);
+            let loop_kind = match kind & 3 {
+                _ if loop_len == 0 || loop_start + loop_len > data.len() => LoopKind::None,
+                1 => LoopKind::Forward,
+                2 => LoopKind::PingPong,
+                _ => LoopKind::None,
+            };
+            samples.push(Sample {
+                data,
+                loop_start,
+                loop_len,
+                loop_kind,
+                volume,
+                finetune,
+                relative_note,
+            });
+        }
+        instruments.push(Instrument {
+            sample_map,
+            samples,
+            envelope,
+            fadeout,
+        });
+    }
+
+    Ok(Module {
+        name,
+        format: Format::Xm { linear: flags & 1 != 0 },
+        channels,
+        orders,
+        restart: if restart < song_length { restart } else { 0 },
+        patterns,
+        instruments,
+        speed,
+        tempo,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn padded(text: &[u8], len: usize) -> Vec<u8> {
+        let mut bytes = text.to_vec();
+        bytes.resize(len, 0);
+        bytes
+    }
+
+    /// A two-pattern MOD. The first sample is `len` bytes counting up from
+    /// 0 and loops `loop_start..loop_start + loop_len`, `cells` are
+    /// (row, channel, bytes) in pattern 0.
+    fn mod_file(tag: &[u8; 4], channels: usize, sample: (usize, usize, usize), cells: &[(usize, usize, [u8; 4])]) -> Vec<u8> {
+        let mut bytes = padded(b"mod song", 20);
+        for i in 0..31 {
+            let (len, loop_start, loop_len) = if i == 0 { sample } else { (0, 0, 0) };
+            bytes.extend([0u8; 22]);
+            bytes.extend(((len / 2) as u16).to_be_bytes());
+            bytes.push(0x0F); // finetune -1/8 semitone
+            bytes.push(80); // louder than allowed
+            bytes.extend(((loop_start / 2) as u16).to_be_bytes());
+            bytes.extend(((loop_len / 2) as u16).to_be_bytes());
+        }
+        bytes.push(2);
+        bytes.push(127); // restart past the end
+        let mut orders = [0u8; 128];
+        orders[1] = 1;
+        bytes.extend(orders);
+        bytes.extend(tag);
+        let mut patterns = vec![0u8; 2 * 64 * channels * 4];
+        for (row, channel, cell) in cells {
+            let at = (row * channels + channel) * 4;
+            patterns[at..at + 4].copy_from_slice(cell);
+        }
+        bytes.extend(patterns);
+        bytes.extend((0..sample.0).map(|i| i as u8));
+        bytes
+    }
+
+    fn mod_error(bytes: &[u8]) -> String {
+        parse_mod(bytes).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn mod_periods_map_to_xm_notes() {
+        // ProTracker C-1, C-2 and B-3 are XM's C-3, C-4 and B-5
+        assert_eq!(mod_period_to_note(856), 37);
+        assert_eq!(mod_period_to_note(428), 49);
+        assert_eq!(mod_period_to_note(113), 72);
+        // slightly detuned or out of range periods snap to the nearest
+        assert_eq!(mod_period_to_note(431), 49);
+        assert_eq!(mod_period_to_note(1000), 37);
+        assert_eq!(mod_period_to_note(0), 0);
+    }
+
+    #[test]
+    fn parses_a_mod() {
+        // instrument 17, period 428, effect C20
+        let module = parse_mod(&mod_file(b"M.K.", 4, (100, 20, 40), &[(2, 1, [0x11, 0xAC, 0x1C, 0x20])])).unwrap();
+        assert_eq!(module.name, "mod song");
+        assert_eq!(module.format, Format::Mod);
+        assert_eq!(module.channels, 4);
+        assert_eq!(module.orders, vec![0, 1]);
+        assert_eq!(module.restart, 0);
+        assert_eq!(module.patterns.len(), 2);
+        assert_eq!((module.speed, module.tempo), (6, 125));
+
+        let cell = module.cell(0, 2, 1);
+        assert_eq!((cell.note, cell.instrument, cell.volume, cell.effect, cell.param), (49, 17, 0, 0x0C, 0x20));
+        assert_eq!(module.cell(0, 2, 0).note, 0);
+        assert_eq!(module.cell(5, 0, 0).note, 0);
+
+        assert_eq!(module.instruments.len(), 31);
+        let sample = &module.instruments[0].samples[0];
+        assert_eq!(sample.data.len(), 100);
+        assert_eq!(sample.data[5], 5.0 / 128.0);
+        assert_eq!((sample.volume, sample.finetune), (64, -16));
+        assert_eq!((sample.loop_kind, sample.loop_start, sample.loop_len), (LoopKind::Forward, 20, 40));
+        assert!(module.instruments[1].samples[0].data.is_empty());
+    }
+
+    #[test]
+    fn mod_loops_must_fit_the_sample() {
+        for (sample, expected) in [
+            ((100, 0, 100), (LoopKind::Forward, 0, 100)),
+            // a one word loop means no loop
+            ((100, 0, 2), (LoopKind::None, 0, 0)),
+            ((100, 80, 40), (LoopKind::None, 0, 0)),
+        ] {
+            let module = parse_mod(&mod_file(b"M.K.", 4, sample, &[])).unwrap();
+            let s = &module.instruments[0].samples[0];
+            assert_eq!((s.loop_kind, s.loop_start, s.loop_len), expected, "{:?}", sample);
+        }
+    }
+
+    #[test]
+    fn short_mod_samples_play_what_is_there() {
+        let bytes = mod_file(b"M.K.", 4, (100, 0, 100), &[]);
+        let module = parse_mod(&bytes[..bytes.len() - 30]).unwrap();
+        let sample = &module.instruments[0].samples[0];
+        assert_eq!(sample.data.len(), 70);
+        assert_eq!(sample.loop_kind, LoopKind::None);
+    }
+
+    #[test]
+    fn mod_channel_count_comes_from_the_tag() {
+        assert_eq!(parse_mod(&mod_file(b"6CHN", 6, (0, 0, 0), &[])).unwrap().channels, 6);
+        assert_eq!(parse_mod(&mod_file(b"FLT8", 8, (0, 0, 0), &[])).unwrap().channels, 8);
+        assert_eq!(parse_mod(&mod_file(b"12CH", 12, (0, 0, 0), &[])).unwrap().channels, 12);
+        assert_eq!(mod_error(&mod_file(b"00CH", 4, (0, 0, 0), &[])), "MOD has 0 channels");
+        assert!(mod_error(&mod_file(b"ABCD", 4, (0, 0, 0), &[])).starts_with("not a MOD"));
+        assert!(mod_error(&[0; 100]).starts_with("not a MOD"));
+        // the tag promises more patterns than the file holds
+        let bytes = mod_file(b"M.K.", 4, (0, 0, 0), &[]);
+        assert!(mod_error(&bytes[..2000]).starts_with("file ends early"));
+    }
+
+    /// A two-row, two-channel linear XM with one instrument holding an
+    /// 8-bit forward looped sample and a 16-bit ping-pong one.
+    fn xm_file(channels: u16) -> Vec<u8> {
+        let mut bytes = b"Extended Module: ".to_vec();
+        bytes.extend(padded(b"xm song", 20));
+        bytes.push(0x1A);
+        bytes.extend([0u8; 20]);
+        bytes.extend(0x0104u16.to_le_bytes());
+        bytes.extend(276u32.to_le_bytes());
+        // song length, restart, channels, patterns, instruments, flags, speed, tempo
+        for v in [3u16, 1, channels, 1, 1, 1, 3, 150] {
+            bytes.extend(v.to_le_bytes());
+        }
+        bytes.extend(padded(&[0, 0, 0], 256));
+
+        let packed = [
+            0x9B, 49, 1, 0x0F, 0x03, // note, instrument, effect and param
+            0x80, // nothing
+            97, 0, 0x40, 0x0E, 0x62, // all five, unpacked
+            0x84, 0x20, // volume only
+        ];
+        bytes.extend(9u32.to_le_bytes());
+        bytes.push(0);
+        bytes.extend(2u16.to_le_bytes());
+        bytes.extend((packed.len() as u16).to_le_bytes());
+        bytes.extend(packed);
+
+        let start = bytes.len();
+        bytes.extend(263u32.to_le_bytes());
+        bytes.extend([0u8; 23]);
+        bytes.extend(2u16.to_le_bytes());
+        bytes.extend(40u32.to_le_bytes());
+        let mut sample_map = [0u8; 96];
+        sample_map[48..].fill(1);
+        bytes.extend(sample_map);
+        for i in 0..12 {
+            let (x, y) = [(0u16, 64u16), (10, 32), (20, 0)].get(i).cloned().unwrap_or((0, 0));
+            bytes.extend(x.to_le_bytes());
+            bytes.extend(y.to_le_bytes());
+        }
+        bytes.extend([0u8; 48]);
+        // 3 points, sustain on the second, envelope and sustain enabled
+        bytes.extend([3, 0, 1, 0, 2, 0, 0, 0, 0x03]);
+        bytes.extend([0u8; 5]);
+        bytes.extend(256u16.to_le_bytes());
+        bytes.resize(start + 263, 0);
+
+        // length, loop start, loop length, volume, finetune, type, panning, relative note
+        for (len, loop_start, loop_len, kind, relative_note) in [(4u32, 1u32, 2u32, 0x01u8, 0u8), (8, 2, 4, 0x12, 12)] {
+            bytes.extend(len.to_le_bytes());
+            bytes.extend(loop_start.to_le_bytes());
+            bytes.extend(loop_len.to_le_bytes());
+            bytes.extend([48, 0xF0, kind, 128, relative_note, 0]);
+            bytes.extend([0u8; 22]);
+        }
+        bytes.extend([10, 5, -20i8 as u8, 1]);
+        for delta in [1000i16, 1000, -3000, 0] {
+            bytes.extend(delta.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_an_xm() {
+        let module = parse_xm(&xm_file(2)).unwrap();
+        assert_eq!(module.name, "xm song");
+        assert_eq!(module.format, Format::Xm { linear: true });
+        assert_eq!((module.channels, module.restart), (2, 1));
+        assert_eq!(module.orders, vec![0, 0, 0]);
+        assert_eq!((module.speed, module.tempo), (3, 150));
+
+        let cells: Vec<(u8, u8, u8, u8, u8)> = module.patterns[0]
+            .cells
+            .iter()
+            .map(|c| (c.note, c.instrument, c.volume, c.effect, c.param))
+            .collect();
+        assert_eq!(
+            cells,
+            vec![(49, 1, 0, 0x0F, 0x03), (0, 0, 0, 0, 0), (KEY_OFF, 0, 0x40, 0x0E, 0x62), (0, 0, 0x20, 0, 0)]
+        );
+
+        let instrument = &module.instruments[0];
+        assert_eq!(instrument.sample_index(48), Some(0));
+        assert_eq!(instrument.sample_index(49), Some(1));
+        assert_eq!(instrument.fadeout, 256);
+        let envelope = instrument.envelope.as_ref().unwrap();
+        assert_eq!(envelope.points, vec![(0, 64), (10, 32), (20, 0)]);
+        assert_eq!((envelope.sustain, envelope.loop_range), (Some(1), None));
+        assert_eq!(envelope.value_at(5), 0.75);
+    }
+
+    #[test]
+    fn xm_samples_are_delta_decoded_with_loops_in_frames() {
+        let module = parse_xm(&xm_file(2)).unwrap();
+        let samples = &module.instruments[0].samples;
+
+        let eight = &samples[0];
+        assert_eq!(eight.data, vec![10.0 / 128.0, 15.0 / 128.0, -5.0 / 128.0, -4.0 / 128.0]);
+        assert_eq!((eight.loop_kind, eight.loop_start, eight.loop_len), (LoopKind::Forward, 1, 2));
+        assert_eq!((eight.volume, eight.finetune, eight.relative_note), (48, -16, 0));
+
+        let sixteen = &samples[1];
+        assert_eq!(sixteen.data, vec![1000.0 / 32768.0, 2000.0 / 32768.0, -1000.0 / 32768.0, -1000.0 / 32768.0]);
+        assert_eq!((sixteen.loop_kind, sixteen.loop_start, sixteen.loop_len), (LoopKind::PingPong, 1, 2));
+        assert_eq!(sixteen.relative_note, 12);
+    }
+
+    #[test]
+    fn xm_channel_count_is_checked() {
+        for channels in [0, 65] {
+            let error = parse_xm(&xm_file(channels)).err().unwrap().to_string();
+            assert_eq!(error, format!("XM has {} channels", channels));
+        }
+        let bytes = xm_file(2);
+        assert!(parse_xm(&bytes[..bytes.len() - 3]).is_err());
+    }
+}
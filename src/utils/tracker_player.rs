@@ -0,0 +1,801 @@
+use std::collections::VecDeque;
+use std::f64::consts::TAU;
+use raylib::prelude::*;
+use crate::utils::music::{open_stream, to_i16, STREAM_FRAMES};
+use crate::utils::tracker::{Cell, Format, LoopKind, Module, KEY_OFF};
+
+/// Reported as each row starts, so attacks can be lined up with the song.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RowEvent {
+    pub time: f64, // seconds on the player's clock
+    /// Rows played since the start, counting repeats and jumps back.
+    pub index: usize,
+    pub order: usize,
+    pub pattern: usize,
+    pub row: usize,
+}
+
+/// Period of `note` (0 is C-0) in the module's pitch units, four times finer
+/// than ProTracker's so MOD and XM slides use the same numbers.
+fn period_of(format: Format, note: i32, finetune: i32) -> f64 {
+    let n = note as f64 + finetune as f64 / 128.0;
+    match format {
+        Format::Xm { linear: true } => 7680.0 - n * 64.0,
+        _ => 1712.0 * 2f64.powf((48.0 - n) / 12.0),
+    }
+}
+
+fn frequency(format: Format, period: f64) -> f64 {
+    match format {
+        Format::Xm { linear: true } => 8363.0 * 2f64.powf((4608.0 - period) / 768.0),
+        Format::Xm { linear: false } => 14317456.0 / period,
+        // PAL Amiga clock
+        Format::Mod => 14187578.4 / period,
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Channel {
+    instrument: usize, // 1 based, 0 is none yet
+    sample: Option<(usize, usize)>,
+    playing: bool,
+    pos: f64,
+    backwards: bool,
+    period: f64,
+    porta_target: f64,
+    volume: i32, // 0 to 64
+    key_on: bool,
+    fade: i32, // 0 to 65536
+    envelope_tick: u16,
+
+    // this row's cell, and a copy held back by a note delay
+    effect: u8,
+    param: u8,
+    volume_column: u8,
+    delayed: Option<Cell>,
+
+    // effect memory, for effects given a 0 parameter
+    porta_speed: f64,
+    porta_up: u8,
+    porta_down: u8,
+    volume_slide: u8,
+    offset: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    vibrato_pos: u8,
+    loop_row: usize,
+    loop_count: u8,
+
+    vibrato: f64,
+    arpeggio: i32,
+
+    // worked out once per tick for the mixer
+    step: f64,
+    gain: f32,
+}
+
+impl Channel {
+    fn slide_volume(&mut self, param: u8) {
+        let (up, down) = ((param >> 4) as i32, (param & 0x0F) as i32);
+        self.volume = if up > 0 { self.volume + up } else { self.volume - down }.clamp(0, 64);
+    }
+
+    fn tone_porta(&mut self) {
+        if self.period < self.porta_target {
+            self.period = (self.period + self.porta_speed).min(self.porta_target);
+        } else {
+            self.period = (self.period - self.porta_speed).max(self.porta_target);
+        }
+    }
+
+    fn vibrato(&mut self) {
+        self.vibrato = (self.vibrato_pos as f64 / 64.0 * TAU).sin() * self.vibrato_depth as f64 * 8.0;
+        self.vibrato_pos = (self.vibrato_pos + self.vibrato_speed) % 64;
+    }
+}
+
+/// Plays a `Module` into a buffer of samples, following its patterns,
+/// effects and loops. Independent of raylib so it can render offline too.
+pub(crate) struct Sequencer {
+    module: Module,
+    sample_rate: u32,
+    channels: Vec<Channel>,
+    order: usize,
+    row: usize,
+    tick: u8,
+    speed: u8,
+    tempo: u8,
+    global_volume: i32, // 0 to 64
+    global_slide: u8,
+    // where to go after this row, from B, D and E6 effects
+    jump_order: Option<usize>,
+    break_row: Option<usize>,
+    loop_to: Option<usize>,
+    row_delay: u8,
+    repeating: bool,
+    tick_left: f64,
+    elapsed: usize,
+    rows_played: usize,
+    events: Vec<RowEvent>,
+}
+
+impl Sequencer {
+    pub(crate) fn new(module: Module, sample_rate: u32) -> Sequencer {
+        let mut sequencer = Sequencer {
+            channels: vec![Channel::default(); module.channels],
+            sample_rate,
+            order: 0,
+            row: 0,
+            tick: 0,
+            speed: module.speed,
+            tempo: module.tempo,
+            global_volume: 64,
+            global_slide: 0,
+            jump_order: None,
+            break_row: None,
+            loop_to: None,
+            row_delay: 0,
+            repeating: false,
+            tick_left: 0.0,
+            elapsed: 0,
+            rows_played: 0,
+            events: Vec::new(),
+            module,
+        };
+        sequencer.rewind();
+        sequencer
+    }
+
+    /// Back to the first row of the first order, all channels silent.
+    pub(crate) fn rewind(&mut self) {
+        self.channels = vec![Channel::default(); self.module.channels];
+        self.order = 0;
+        self.row = 0;
+        self.tick = 0;
+        self.speed = self.module.speed;
+        self.tempo = self.module.tempo;
+        self.global_volume = 64;
+        self.jump_order = None;
+        self.break_row = None;
+        self.loop_to = None;
+        self.row_delay = 0;
+        self.repeating = false;
+        self.tick_left = 0.0;
+        self.elapsed = 0;
+        self.rows_played = 0;
+        self.events.clear();
+    }
+
+    /// Samples rendered since the start.
+    pub(crate) fn elapsed(&self) -> usize {
+        self.elapsed
+    }
+
+    /// Rows started since the last call, oldest first.
+    pub(crate) fn take_events(&mut self) -> Vec<RowEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Fills `out` with the next samples of the song, -1 to 1. The song
+    /// loops back to its restart position forever.
+    pub(crate) fn render(&mut self, out: &mut [f32]) {
+        // scaled so a few channels at full volume don't clip straight away
+        let mix_gain = 1.0 / (self.channels.len().max(1) as f32).sqrt();
+        for sample in out.iter_mut() {
+            if self.tick_left <= 0.0 {
+                self.run_tick();
+                // a tick is 2.5ms at 1 BPM, the way trackers count tempo
+                self.tick_left += self.sample_rate as f64 * 2.5 / self.tempo.max(1) as f64;
+            }
+            self.tick_left -= 1.0;
+            *sample = self.mix() * mix_gain;
+            self.elapsed += 1;
+        }
+    }
+
+    fn pattern(&self) -> usize {
+        self.module.orders.get(self.order).cloned().unwrap_or(0) as usize
+    }
+
+    fn pattern_rows(&self, order: usize) -> usize {
+        let pattern = self.module.orders.get(order).cloned().unwrap_or(0) as usize;
+        self.module.patterns.get(pattern).map_or(64, |p| p.rows.max(1))
+    }
+
+    fn run_tick(&mut self) {
+        if self.tick == 0 && !self.repeating {
+            self.start_row();
+        } else {
+            for ch in 0..self.channels.len() {
+                self.tick_effects(ch);
+            }
+        }
+        self.update_channels();
+
+        self.tick += 1;
+        if self.tick >= self.speed.max(1) {
+            self.tick = 0;
+            if self.row_delay > 0 {
+                self.row_delay -= 1;
+                self.repeating = true;
+            } else {
+                self.repeating = false;
+                self.next_row();
+            }
+        }
+    }
+
+    fn next_row(&mut self) {
+        if let Some(row) = self.loop_to.take() {
+            self.row = row;
+            self.jump_order = None;
+            self.break_row = None;
+        } else if self.jump_order.is_some() || self.break_row.is_some() {
+            self.order = self.jump_order.take().unwrap_or(self.order + 1);
+            self.row = self.break_row.take().unwrap_or(0);
+            for c in self.channels.iter_mut() {
+                c.loop_row = 0;
+            }
+        } else {
+            self.row += 1;
+            if self.row >= self.pattern_rows(self.order) {
+                self.order += 1;
+                self.row = 0;
+            }
+        }
+        if self.order >= self.module.orders.len() {
+            self.order = self.module.restart;
+        }
+        if self.row >= self.pattern_rows(self.order) {
+            self.row = 0;
+        }
+    }
+
+    fn start_row(&mut self) {
+        let pattern = self.pattern();
+        self.events.push(RowEvent {
+            time: self.elapsed as f64 / self.sample_rate as f64,
+            index: self.rows_played,
+            order: self.order,
+            pattern,
+            row: self.row,
+        });
+        self.rows_played += 1;
+
+        for ch in 0..self.channels.len() {
+            let cell = self.module.cell(pattern, self.row, ch);
+            let c = &mut self.channels[ch];
+            c.effect = cell.effect;
+            c.param = cell.param;
+            c.volume_column = cell.volume;
+            c.arpeggio = 0;
+            if !matches!(cell.effect, 4 | 6) {
+                c.vibrato = 0.0;
+            }
+            if cell.effect == 0x0E && cell.param >> 4 == 0x0D && cell.param & 0x0F > 0 {
+                c.delayed = Some(cell);
+            } else {
+                c.delayed = None;
+                self.play_cell(ch, cell);
+            }
+            self.row_effects(ch, cell);
+        }
+    }
+
+    /// Triggers the cell's note and instrument and applies its volume column.
+    fn play_cell(&mut self, ch: usize, cell: Cell) {
+        let format = self.module.format;
+        let porta = matches!(cell.effect, 3 | 5) || cell.volume >> 4 == 0x0F;
+        let c = &mut self.channels[ch];
+        if cell.instrument > 0 {
+            c.instrument = cell.instrument as usize;
+        }
+        let instrument = c.instrument.checked_sub(1).and_then(|i| self.module.instruments.get(i).map(|inst| (i, inst)));
+
+        if cell.note == KEY_OFF {
+            c.key_on = false;
+            if instrument.is_none_or(|(_, inst)| inst.envelope.is_none()) {
+                c.volume = 0;
+            }
+        } else if cell.note > 0 {
+            if let Some((ii, inst)) = instrument {
+                if let Some(si) = inst.sample_index(cell.note) {
+                    let sample = &inst.samples[si];
+                    let note = cell.note as i32 - 1 + sample.relative_note as i32;
+                    let period = period_of(format, note, sample.finetune as i32);
+                    c.porta_target = period;
+                    if !porta || !c.playing {
+                        c.sample = Some((ii, si));
+                        c.period = period;
+                        c.pos = 0.0;
+                        c.backwards = false;
+                        c.playing = true;
+                        c.key_on = true;
+                        c.fade = 65536;
+                        c.envelope_tick = 0;
+                        c.vibrato_pos = 0;
+                        if cell.effect == 9 {
+                            if cell.param > 0 {
+                                c.offset = cell.param;
+                            }
+                            c.pos = c.offset as f64 * 256.0;
+                        }
+                    }
+                }
+            }
+        }
+
+        // an instrument number on its own resets the volume and envelope
+        if cell.instrument > 0 && cell.note != KEY_OFF {
+            if let Some(sample) = c.sample.and_then(|(ii, si)| self.module.instruments.get(ii)?.samples.get(si)) {
+                c.volume = sample.volume as i32;
+                c.key_on = true;
+                c.fade = 65536;
+                c.envelope_tick = 0;
+            }
+        }
+
+        let (kind, value) = (cell.volume >> 4, cell.volume & 0x0F);
+        match kind {
+            0x1..=0x4 => c.volume = (cell.volume - 0x10) as i32,
+            0x5 if cell.volume == 0x50 => c.volume = 64,
+            0x8 => c.volume = (c.volume - value as i32).max(0),
+            0x9 => c.volume = (c.volume + value as i32).min(64),
+            0xF if value > 0 => c.porta_speed = value as f64 * 16.0 * 4.0,
+            _ => {}
+        }
+    }
+
+    /// Effects that happen once, on the first tick of the row.
+    fn row_effects(&mut self, ch: usize, cell: Cell) {
+        let (x, y) = (cell.param >> 4, cell.param & 0x0F);
+        let c = &mut self.channels[ch];
+        match cell.effect {
+            0x1 if cell.param > 0 => c.porta_up = cell.param,
+            0x2 if cell.param > 0 => c.porta_down = cell.param,
+            0x3 if cell.param > 0 => c.porta_speed = cell.param as f64 * 4.0,
+            0x4 => {
+                if x > 0 {
+                    c.vibrato_speed = x;
+                }
+                if y > 0 {
+                    c.vibrato_depth = y;
+                }
+            }
+            0x5 | 0x6 | 0xA if cell.param > 0 => c.volume_slide = cell.param,
+            0x9 if cell.param > 0 => c.offset = cell.param,
+            0xB => self.jump_order = Some(cell.param as usize),
+            0xC => c.volume = (cell.param as i32).min(64),
+            0xD => self.break_row = Some((x * 10 + y) as usize),
+            0xE => match x {
+                0x1 => c.period -= y as f64 * 4.0,
+                0x2 => c.period += y as f64 * 4.0,
+                0x6 if y == 0 => c.loop_row = self.row,
+                0x6 => {
+                    if c.loop_count == 0 {
+                        c.loop_count = y;
+                        self.loop_to = Some(c.loop_row);
+                    } else {
+                        c.loop_count -= 1;
+                        if c.loop_count > 0 {
+                            self.loop_to = Some(c.loop_row);
+                        }
+                    }
+                }
+                0xA => c.volume = (c.volume + y as i32).min(64),
+                0xB => c.volume = (c.volume - y as i32).max(0),
+                0xC if y == 0 => c.volume = 0,
+                0xE if !self.repeating => self.row_delay = y,
+                _ => {}
+            },
+            0xF if cell.param == 0 => {}
+            0xF if cell.param < 32 => self.speed = cell.param,
+            0xF => self.tempo = cell.param,
+            // XM only from here on
+            0x10 => self.global_volume = (cell.param as i32).min(64),
+            0x11 if cell.param > 0 => self.global_slide = cell.param,
+            0x14 if cell.param == 0 => self.key_off(ch),
+            _ => {}
+        }
+    }
+
+    /// Effects that keep going on every tick after the first.
+    fn tick_effects(&mut self, ch: usize) {
+        let tick = self.tick;
+        let c = &mut self.channels[ch];
+        let (x, y) = (c.param >> 4, c.param & 0x0F);
+        match c.effect {
+            0x0 if c.param > 0 => c.arpeggio = [0, x as i32, y as i32][tick as usize % 3],
+            0x1 => c.period -= c.porta_up as f64 * 4.0,
+            0x2 => c.period += c.porta_down as f64 * 4.0,
+            0x3 => c.tone_porta(),
+            0x4 => c.vibrato(),
+            0x5 => {
+                c.tone_porta();
+                c.slide_volume(c.volume_slide);
+            }
+            0x6 => {
+                c.vibrato();
+                c.slide_volume(c.volume_slide);
+            }
+            0xA => c.slide_volume(c.volume_slide),
+            0xE => match x {
+                0x9 if y > 0 && tick.is_multiple_of(y) => c.pos = 0.0,
+                0xC if tick == y => c.volume = 0,
+                0xD if tick == y => {
+                    if let Some(cell) = c.delayed.take() {
+                        self.play_cell(ch, cell);
+                    }
+                }
+                _ => {}
+            },
+            0x11 => {
+                let (up, down) = ((self.global_slide >> 4) as i32, (self.global_slide & 0x0F) as i32);
+                self.global_volume = if up > 0 { self.global_volume + up } else { self.global_volume - down }.clamp(0, 64);
+            }
+            0x14 if tick == c.param => self.key_off(ch),
+            _ => {}
+        }
+
+        let c = &mut self.channels[ch];
+        let value = (c.volume_column & 0x0F) as i32;
+        match c.volume_column >> 4 {
+            0x6 => c.volume = (c.volume - value).max(0),
+            0x7 => c.volume = (c.volume + value).min(64),
+            0xF => c.tone_porta(),
+            _ => {}
+        }
+    }
+
+    fn key_off(&mut self, ch: usize) {
+        let c = &mut self.channels[ch];
+        c.key_on = false;
+        let has_envelope = c
+            .sample
+            .and_then(|(ii, _)| self.module.instruments.get(ii))
+            .is_some_and(|inst| inst.envelope.is_some());
+        if !has_envelope {
+            c.volume = 0;
+        }
+    }
+
+    /// Works out each channel's pitch and loudness for the coming tick.
+    fn update_channels(&mut self) {
+        let format = self.module.format;
+        let rate = self.sample_rate as f64;
+        let global = self.global_volume as f32 / 64.0;
+        for c in self.channels.iter_mut() {
+            let Some(inst) = c.sample.and_then(|(ii, _)| self.module.instruments.get(ii)) else {
+                c.gain = 0.0;
+                continue;
+            };
+            let mut envelope_gain = 1.0;
+            if let Some(envelope) = inst.envelope.as_ref() {
+                envelope_gain = envelope.value_at(c.envelope_tick);
+                let sustaining = c.key_on && envelope.sustain.and_then(|s| envelope.tick_of(s)) == Some(c.envelope_tick);
+                if !sustaining {
+                    c.envelope_tick = c.envelope_tick.saturating_add(1);
+                    if let Some((start, end)) = envelope.loop_range {
+                        if envelope.tick_of(end) == Some(c.envelope_tick) {
+                            c.envelope_tick = envelope.tick_of(start).unwrap_or(0);
+                        }
+                    }
+                }
+                if !c.key_on {
+                    c.fade = (c.fade - inst.fadeout as i32).max(0);
+                }
+            }
+            c.gain = c.volume as f32 / 64.0 * envelope_gain * c.fade as f32 / 65536.0 * global;
+
+            let period = (c.period + c.vibrato).max(1.0);
+            c.step = frequency(format, period) * 2f64.powf(c.arpeggio as f64 / 12.0) / rate;
+        }
+    }
+
+    /// One output sample from every channel, linearly interpolated.
+    fn mix(&mut self) -> f32 {
+        let mut mixed = 0.0;
+        for c in self.channels.iter_mut() {
+            if !c.playing {
+                continue;
+            }
+            let Some(sample) = c.sample.and_then(|(ii, si)| self.module.instruments.get(ii)?.samples.get(si)) else {
+                continue;
+            };
+            let len = sample.data.len();
+            let loop_end = sample.loop_start + sample.loop_len;
+            if len == 0 || c.pos >= len as f64 {
+                c.playing = false;
+                continue;
+            }
+
+            let i = c.pos as usize;
+            let next = match sample.loop_kind {
+                LoopKind::Forward if i + 1 >= loop_end => sample.loop_start,
+                _ => (i + 1).min(len - 1),
+            };
+            let f = c.pos.fract() as f32;
+            mixed += (sample.data[i] + (sample.data[next] - sample.data[i]) * f) * c.gain;
+
+            if c.backwards {
+                c.pos -= c.step;
+            } else {
+                c.pos += c.step;
+            }
+            match sample.loop_kind {
+                LoopKind::None => c.playing = c.pos < len as f64,
+                LoopKind::Forward => {
+                    while c.pos >= loop_end as f64 {
+                        c.pos -= sample.loop_len as f64;
+                    }
+                }
+                LoopKind::PingPong => {
+                    let (start, end) = (sample.loop_start as f64, loop_end as f64 - 1.0);
+                    if !c.backwards && c.pos >= end {
+                        c.pos = (end * 2.0 - c.pos).max(start);
+                        c.backwards = true;
+                    } else if c.backwards && c.pos < start {
+                        c.pos = (start * 2.0 - c.pos).min(end);
+                        c.backwards = false;
+                    }
+                }
+            }
+        }
+        mixed
+    }
+}
+
+/// Streams a tracker module, reporting each row as it's heard.
+pub(crate) struct TrackerPlayer<'aud> {
+    stream: AudioStream<'aud>,
+    sample_rate: u32,
+    sequencer: Option<Sequencer>,
+    playing: bool,
+    volume: f32,
+    rows: VecDeque<RowEvent>,
+    mixed: Vec<f32>,
+    buffer: Vec<i16>,
+}
+
+impl<'aud> TrackerPlayer<'aud> {
+    pub(crate) fn new(audio: &'aud RaylibAudio, sample_rate: u32) -> TrackerPlayer<'aud> {
+        let mut stream = open_stream(audio, sample_rate, 1);
+        stream.play();
+        TrackerPlayer {
+            stream,
+            sample_rate,
+            sequencer: None,
+            playing: false,
+            volume: 1.0,
+            rows: VecDeque::new(),
+            mixed: vec![0.0; STREAM_FRAMES],
+            buffer: vec![0; STREAM_FRAMES],
+        }
+    }
+
+    /// Loads a `.mod` or `.xm` file, replacing whatever was loaded before.
+    pub(crate) fn load(&mut self, filename: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let module = Module::load(filename)?;
+        self.sequencer = Some(Sequencer::new(module, self.sample_rate));
+        self.playing = false;
+        self.rows.clear();
+        Ok(())
+    }
+
+    pub(crate) fn is_loaded(&self) -> bool {
+        self.sequencer.is_some()
+    }
+
+    pub(crate) fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Starts the song over from the top.
+    pub(crate) fn play(&mut self) {
+        if let Some(sequencer) = self.sequencer.as_mut() {
+            sequencer.rewind();
+            self.rows.clear();
+            self.playing = true;
+        }
+    }
+
+    pub(crate) fn stop(&mut self) {
+        self.playing = false;
+        self.rows.clear();
+    }
+
+    pub(crate) fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Seconds since `play`, as heard through the speakers. Same idea as
+    /// `MusicPlayer::clock`, and what `RowEvent::time` is measured on.
+    pub(crate) fn clock(&self) -> Option<f64> {
+        if !self.playing {
+            return None;
+        }
+        let heard = self.sequencer.as_ref()?.elapsed().saturating_sub(STREAM_FRAMES);
+        Some(heard as f64 / self.sample_rate as f64)
+    }
+
+    /// Every row that has started playing since the last call. Call once per
+    /// frame, after `update`.
+    pub(crate) fn due_rows(&mut self) -> Vec<RowEvent> {
+        let Some(clock) = self.clock() else {
+            return Vec::new();
+        };
+        let due = self.rows.iter().take_while(|r| r.time <= clock).count();
+        self.rows.drain(..due).collect()
+    }
+
+    /// Feeds raylib more audio. Call once per frame.
+    pub(crate) fn update(&mut self) {
+        while self.stream.is_processed() {
+            self.fill_buffer();
+            self.stream.update(&self.buffer);
+        }
+    }
+
+    fn fill_buffer(&mut self) {
+        match self.sequencer.as_mut() {
+            Some(sequencer) if self.playing => {
+                sequencer.render(&mut self.mixed);
+                self.rows.extend(sequencer.take_events());
+            }
+            _ => self.mixed.fill(0.0),
+        }
+        for (out, v) in self.buffer.iter_mut().zip(self.mixed.iter()) {
+            *out = to_i16(v * self.volume);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tracker::{Instrument, Pattern, Sample};
+
+    /// A tick is 20 samples at this rate and tempo 125.
+    const RATE: u32 = 1000;
+    const TICK: usize = 20;
+
+    fn instrument(relative_note: i8) -> Instrument {
+        Instrument {
+            sample_map: [0; 96],
+            samples: vec![Sample {
+                data: vec![0.5; 1000],
+                loop_start: 0,
+                loop_len: 0,
+                loop_kind: LoopKind::None,
+                volume: 64,
+                finetune: 0,
+                relative_note,
+            }],
+            envelope: None,
+            fadeout: 0,
+        }
+    }
+
+    /// One-channel patterns of the given lengths, with `cells` as
+    /// (pattern, row, cell). Plays a row per tick.
+    fn module(format: Format, rows: &[usize], orders: &[u8], cells: &[(usize, usize, Cell)]) -> Module {
+        let mut patterns: Vec<Pattern> = rows
+            .iter()
+            .map(|r| Pattern {
+                rows: *r,
+                cells: vec![Cell::default(); *r],
+            })
+            .collect();
+        for (pattern, row, cell) in cells {
+            patterns[*pattern].cells[*row] = *cell;
+        }
+        Module {
+            name: String::new(),
+            format,
+            channels: 1,
+            orders: orders.to_vec(),
+            restart: 1,
+            patterns,
+            instruments: vec![instrument(0), instrument(12)],
+            speed: 1,
+            tempo: 125,
+        }
+    }
+
+    fn effect(effect: u8, param: u8) -> Cell {
+        Cell {
+            effect,
+            param,
+            ..Cell::default()
+        }
+    }
+
+    /// (order, row) of the next `count` rows.
+    fn play(sequencer: &mut Sequencer, count: usize) -> Vec<(usize, usize)> {
+        sequencer.render(&mut vec![0.0; count * TICK]);
+        let events = sequencer.take_events();
+        assert_eq!(events.len(), count);
+        events.iter().map(|e| (e.order, e.row)).collect()
+    }
+
+    #[test]
+    fn rows_advance_through_the_orders_and_restart() {
+        let mut sequencer = Sequencer::new(module(Format::Mod, &[3, 2], &[0, 1, 0], &[]), RATE);
+        assert_eq!(
+            play(&mut sequencer, 10),
+            vec![(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (2, 0), (2, 1), (2, 2), (1, 0), (1, 1)]
+        );
+
+        sequencer.rewind();
+        sequencer.render(&mut vec![0.0; 4 * TICK]);
+        let events = sequencer.take_events();
+        let seen: Vec<(usize, usize, f64)> = events.iter().map(|e| (e.index, e.pattern, e.time)).collect();
+        assert_eq!(seen, vec![(0, 0, 0.0), (1, 0, 0.02), (2, 0, 0.04), (3, 1, 0.06)]);
+    }
+
+    #[test]
+    fn position_jump_goes_to_the_order() {
+        let cells = [(0, 1, effect(0xB, 2))];
+        let mut sequencer = Sequencer::new(module(Format::Mod, &[4, 4, 4], &[0, 1, 2], &cells), RATE);
+        assert_eq!(play(&mut sequencer, 4), vec![(0, 0), (0, 1), (2, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn pattern_break_goes_to_a_decimal_row_of_the_next_order() {
+        let cells = [(0, 1, effect(0xD, 0x12))];
+        let mut sequencer = Sequencer::new(module(Format::Mod, &[4, 16], &[0, 1], &cells), RATE);
+        assert_eq!(play(&mut sequencer, 4), vec![(0, 0), (0, 1), (1, 12), (1, 13)]);
+
+        // past the end of the pattern means its first row
+        let cells = [(0, 0, effect(0xD, 0x20))];
+        let mut sequencer = Sequencer::new(module(Format::Mod, &[4, 16], &[0, 1], &cells), RATE);
+        assert_eq!(play(&mut sequencer, 2), vec![(0, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn jump_and_break_on_one_row_combine() {
+        // B00 on channel 1 and D01 on channel 2 of the last row
+        let mut module = module(Format::Mod, &[4, 4], &[0, 1], &[]);
+        module.channels = 2;
+        for pattern in module.patterns.iter_mut() {
+            pattern.cells = vec![Cell::default(); pattern.rows * 2];
+        }
+        module.patterns[0].cells[6] = effect(0xB, 0);
+        module.patterns[0].cells[7] = effect(0xD, 0x01);
+        let mut sequencer = Sequencer::new(module, RATE);
+        assert_eq!(play(&mut sequencer, 6), vec![(0, 0), (0, 1), (0, 2), (0, 3), (0, 1), (0, 2)]);
+    }
+
+    #[test]
+    fn pattern_loop_repeats_rows() {
+        let cells = [(0, 0, effect(0xE, 0x60)), (0, 1, effect(0xE, 0x62))];
+        let mut sequencer = Sequencer::new(module(Format::Mod, &[4], &[0], &cells), RATE);
+        let rows: Vec<usize> = play(&mut sequencer, 9).iter().map(|r| r.1).collect();
+        assert_eq!(rows, vec![0, 1, 0, 1, 0, 1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn c4_plays_at_8363_hz() {
+        let linear = Format::Xm { linear: true };
+        let amiga = Format::Xm { linear: false };
+        assert!((frequency(linear, period_of(linear, 48, 0)) - 8363.0).abs() < 1e-6);
+        assert!((frequency(amiga, period_of(amiga, 48, 0)) - 8363.0).abs() < 0.5);
+        // ProTracker's C-2 is period 428 on a PAL Amiga
+        assert!((frequency(Format::Mod, period_of(Format::Mod, 48, 0)) - 3546894.6 / 428.0).abs() < 1e-6);
+        // a finetune of a whole semitone is the next note
+        let sharp = frequency(linear, period_of(linear, 48, 128));
+        assert!((sharp - frequency(linear, period_of(linear, 49, 0))).abs() < 1e-6);
+
+        for (instrument, expected) in [(1, 8363.0), (2, 8363.0 * 2.0)] {
+            let cell = Cell {
+                note: 49,
+                instrument,
+                ..Cell::default()
+            };
+            let mut sequencer = Sequencer::new(module(linear, &[4], &[0], &[(0, 0, cell)]), RATE);
+            sequencer.render(&mut [0.0; 1]);
+            let step = sequencer.channels[0].step * RATE as f64;
+            assert!((step - expected).abs() < 1e-6, "{}", step);
+        }
+    }
+}
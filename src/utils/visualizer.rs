@@ -0,0 +1,285 @@
+use raylib::prelude::*;
+use crate::utils::analysis::SpectrumAnalyzer;
+use crate::utils::draw_text_anchor::{draw_text_anchored, TextConfig};
+use crate::utils::loudness::{lufs, to_db, MOMENTARY};
+use crate::utils::music::MusicPlayer;
+use crate::utils::render_graph::{BarGraph, Chart, Fill, Resample, Scale, Series, SeriesStyle, Smoothing};
+use crate::utils::text_wrap::{wrap_text, WrapConfig};
+use crate::utils::thumbnail::{draw_thumbnail, Thumbnail};
+
+const SCR_W: i32 = 1024;
+const SCR_H: i32 = 640;
+/// FFT size, about 46ms at 44.1kHz.
+const WINDOW: usize = 2048;
+/// Highest frequency shown, most of what's above is hiss.
+const MAX_FREQ: f64 = 8000.0;
+/// How much audio the oscilloscope shows, in seconds.
+const SCOPE_SECS: f64 = 0.03;
+/// One column per frame, so about 8.5 seconds of history at 60 FPS.
+const SPECTROGRAM_W: i32 = 512;
+const SPECTROGRAM_H: i32 = 256;
+/// Anything this quiet is black in the spectrogram.
+const FLOOR_DB: f64 = -80.0;
+/// Height of the whole-track waveform along the top.
+const OVERVIEW_H: f32 = 48.0;
+/// How far back the loudness view goes, in seconds.
+const HISTORY_SECS: f64 = 10.0;
+/// Bottom of the loudness view, in dB.
+const QUIETEST_DB: f64 = -60.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum View {
+    All,
+    Spectrum,
+    Scope,
+    Spectrogram,
+    Loudness,
+}
+
+/// A texture that gets a new column of spectrum per frame, drawn so the
+/// newest column is always on the right.
+struct Spectrogram {
+    texture: Texture2D,
+    pixels: Vec<u8>,
+    column: i32,
+}
+
+impl Spectrogram {
+    fn new(rl: &mut RaylibHandle, thread: &RaylibThread) -> Result<Spectrogram, String> {
+        let image = Image::gen_image_color(SPECTROGRAM_W, SPECTROGRAM_H, Color::BLACK);
+        let texture = rl.load_texture_from_image(thread, &image)?;
+        let mut pixels = vec![0u8; (SPECTROGRAM_W * SPECTROGRAM_H * 4) as usize];
+        for alpha in pixels.iter_mut().skip(3).step_by(4) {
+            *alpha = 255;
+        }
+        Ok(Spectrogram {
+            texture,
+            pixels,
+            column: 0,
+        })
+    }
+
+    fn push(&mut self, magnitudes: &[f64]) {
+        for y in 0..SPECTROGRAM_H {
+            // low frequencies at the bottom
+            let bin = ((SPECTROGRAM_H - 1 - y) as usize * magnitudes.len()) / SPECTROGRAM_H as usize;
+            let level = magnitudes.get(bin).map_or(0.0, |m| ((to_db(*m) - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0));
+            let color = Color::color_from_hsv((1.0 - level as f32) * 240.0, 1.0, level as f32);
+            let at = ((y * SPECTROGRAM_W + self.column) * 4) as usize;
+            self.pixels[at..at + 3].copy_from_slice(&[color.r, color.g, color.b]);
+        }
+        self.column = (self.column + 1) % SPECTROGRAM_W;
+        self.texture.update_texture(&self.pixels);
+    }
+
+    fn draw<T: RaylibDraw>(&self, d: &mut T, bounds: Rectangle) {
+        // oldest columns first, from the write position to the end
+        let older = (SPECTROGRAM_W - self.column) as f32;
+        let split = bounds.width * older / SPECTROGRAM_W as f32;
+        let h = SPECTROGRAM_H as f32;
+        let parts = [
+            (Rectangle::new(self.column as f32, 0.0, older, h), Rectangle::new(bounds.x, bounds.y, split, bounds.height)),
+            (
+                Rectangle::new(0.0, 0.0, self.column as f32, h),
+                Rectangle::new(bounds.x + split, bounds.y, bounds.width - split, bounds.height),
+            ),
+        ];
+        for (source, dest) in parts {
+            if source.width > 0.0 {
+                d.draw_texture_pro(&self.texture, source, dest, Vector2::zero(), 0.0, Color::WHITE);
+            }
+        }
+    }
+}
+
+fn draw_scope<T: RaylibDraw>(d: &mut T, samples: &[f32], bounds: Rectangle) {
+    let mid = bounds.y + bounds.height / 2.0;
+    d.draw_line_ex(Vector2::new(bounds.x, mid), Vector2::new(bounds.x + bounds.width, mid), 1.0, Color::DARKGRAY);
+    let step = bounds.width / samples.len().max(2) as f32;
+    let points: Vec<Vector2> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, v)| Vector2::new(bounds.x + i as f32 * step, mid - v.clamp(-1.0, 1.0) * bounds.height / 2.0))
+        .collect();
+    d.draw_line_strip(&points, Color::LIME);
+}
+
+/// `visualize <track.wav>`: plays the track with a live spectrum,
+/// oscilloscope and scrolling spectrogram under a waveform of the whole
+/// track. Keys 1 to 4 switch views, 5 shows the loudness over the last few
+/// seconds and R restarts the track.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let filename = args.first().ok_or("usage: visualize <track.wav>")?;
+    let (mut rl, thread) = raylib::init()
+        .size(SCR_W, SCR_H)
+        .title("VISUALIZER")
+        .build();
+    rl.set_target_fps(60);
+
+    let audio = RaylibAudio::init_audio_device()?;
+    let mut music = MusicPlayer::new(&audio, 44100);
+    music.load("track", filename)?;
+    music.play("track", 0.0);
+    let overview = Thumbnail::load_cached(filename)?;
+    // two pixels per bar, the zoom never changes so this only happens once
+    let overview_peaks = overview.summarize(0.0, overview.duration_secs(), SCR_W as usize / 2);
+
+    let font = rl.load_font_ex(&thread, "resources/LINESeedSans_Bd.ttf", 64, None)?;
+    let text_config = TextConfig {
+        spacing: 0.0,
+        tint: Color::GRAY,
+        paragraph_align: 0.5,
+        anchor_x: 0.5,
+        anchor_y: 1.0,
+        line_spacing: 1.0,
+    };
+    let help = wrap_text(
+        &font,
+        "1 all   2 spectrum   3 scope   4 spectrogram   5 loudness   R restart",
+        24.0,
+        text_config.spacing,
+        &WrapConfig {
+            max_width: SCR_W as f32 - 32.0,
+            max_lines: Some(2),
+            ellipsis: true,
+        },
+    );
+
+    let mut analyzer = SpectrumAnalyzer::new(44100, WINDOW, MAX_FREQ);
+    let mut spectrogram = Spectrogram::new(&mut rl, &thread)?;
+    let mut spectrum = BarGraph::new(0, SCR_W, SCR_H, SCR_H, 64);
+    spectrum.gap = 2;
+    spectrum.scale = Scale::Log;
+    spectrum.resample = Resample::Max;
+    spectrum.smoothing = Some(Smoothing { attack: 0.0, decay: 0.85 });
+    spectrum.fill = Fill::Gradient {
+        base: Color::DARKPURPLE,
+        tip: Color::SKYBLUE,
+    };
+    spectrum.max_value = Some(0.5);
+
+    let mut chart = Chart::new(Rectangle::new(0.0, 0.0, 0.0, 0.0));
+    chart.x.label = "seconds".to_string();
+    chart.y.label = "dB".to_string();
+    chart.axis_color = Color::GRAY;
+    chart.point_radius = 1.5;
+    // (time, value) pairs, oldest first
+    let mut momentary: Vec<(f64, f64)> = Vec::new();
+    let mut peaks: Vec<(f64, f64)> = Vec::new();
+
+    let mut view = View::All;
+    while !rl.window_should_close() {
+        for (key, pick) in [
+            (KeyboardKey::KEY_ONE, View::All),
+            (KeyboardKey::KEY_TWO, View::Spectrum),
+            (KeyboardKey::KEY_THREE, View::Scope),
+            (KeyboardKey::KEY_FOUR, View::Spectrogram),
+            (KeyboardKey::KEY_FIVE, View::Loudness),
+        ] {
+            if rl.is_key_pressed(key) {
+                view = pick;
+            }
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_R) {
+            music.stop(0.0);
+            music.play("track", 0.0);
+        }
+        music.update();
+
+        // WAVs at 44.1kHz are streamed, so only ask for what's needed
+        let look_back = (WINDOW as f64 / 44100.0).max(SCOPE_SECS).max(MOMENTARY);
+        let now = rl.get_time();
+        let (magnitudes, scope) = match music.recent(look_back) {
+            Some(recent) => {
+                let end = recent.duration_secs();
+                let scale = recent.full_scale();
+                let scope: Vec<f32> = recent
+                    .get_slice_back_seconds(end, SCOPE_SECS)
+                    .iter()
+                    .map(|s| (*s as f64 / scale) as f32)
+                    .collect();
+                // just the last value, measured over the window before it
+                let last = 1.0 / recent.sample_rate() as f64;
+                let level = lufs(&recent, end - last..end, MOMENTARY, 1.0).values.last().cloned();
+                momentary.push((now, level.unwrap_or(QUIETEST_DB).max(QUIETEST_DB)));
+                // the loudest sample since the last frame
+                let frame = recent.get_slice_back_seconds(end, rl.get_frame_time() as f64);
+                let peak = frame.iter().map(|s| (*s as f64 / scale).abs()).fold(0.0, f64::max);
+                peaks.push((now, to_db(peak).max(QUIETEST_DB)));
+                (analyzer.spectrum(&recent, end), scope)
+            }
+            None => (vec![0.0; analyzer.bins()], Vec::new()),
+        };
+        for history in [&mut momentary, &mut peaks] {
+            history.retain(|(t, _)| now - t <= HISTORY_SECS);
+        }
+        spectrum.update(&magnitudes);
+        spectrogram.push(&magnitudes);
+
+        let overview_at = Rectangle::new(0.0, 0.0, SCR_W as f32, OVERVIEW_H);
+        let top = OVERVIEW_H + 8.0;
+        let full = Rectangle::new(0.0, top, SCR_W as f32, SCR_H as f32 - help.size.y - 16.0 - top);
+        let third = full.height / 3.0;
+        let (spectrum_at, scope_at, spectrogram_at) = match view {
+            View::All => (
+                Some(Rectangle::new(0.0, top, full.width, third)),
+                Some(Rectangle::new(0.0, top + third, full.width, third)),
+                Some(Rectangle::new(0.0, top + third * 2.0, full.width, third)),
+            ),
+            View::Spectrum => (Some(full), None, None),
+            View::Scope => (None, Some(full), None),
+            View::Spectrogram => (None, None, Some(full)),
+            View::Loudness => (None, None, None),
+        };
+
+        let mut d = rl.begin_drawing(&thread);
+        d.clear_background(Color::BLACK);
+        draw_thumbnail(&mut d, &overview_peaks, overview_at, 0, Color::DARKGRAY, Color::GRAY);
+        if let Some(position) = music.position() {
+            let x = (position / overview.duration_secs()) as f32 * overview_at.width;
+            d.draw_line_ex(Vector2::new(x, 0.0), Vector2::new(x, OVERVIEW_H), 2.0, Color::SKYBLUE);
+        }
+        if let Some(at) = spectrum_at {
+            spectrum.left = at.x as i32;
+            spectrum.right = (at.x + at.width) as i32;
+            spectrum.baseline = (at.y + at.height) as i32;
+            spectrum.max_height = at.height as i32;
+            spectrum.draw(&mut d);
+        }
+        if let Some(at) = scope_at {
+            draw_scope(&mut d, &scope, at);
+        }
+        if let Some(at) = spectrogram_at {
+            spectrogram.draw(&mut d, at);
+        }
+        if view == View::Loudness {
+            // room for the tick labels and the axis titles
+            let margin = chart.font_size * 4.0;
+            chart.bounds = Rectangle::new(
+                full.x + margin,
+                full.y + chart.font_size * 2.0,
+                full.width - margin * 1.5,
+                full.height - margin,
+            );
+            // in seconds ago, so the newest value is always on the right
+            let ago = |history: &[(f64, f64)]| history.iter().map(|(t, v)| (t - now, *v)).collect();
+            let series = [
+                Series::new("momentary LUFS", ago(&momentary), Color::SKYBLUE, SeriesStyle::Line),
+                Series::new("peak dBFS", ago(&peaks), Color::ORANGE, SeriesStyle::Scatter),
+            ];
+            chart.fit(&series);
+            chart.y.min = QUIETEST_DB;
+            chart.y.max = 0.0;
+            chart.draw(&mut d, &font, &series);
+        }
+        draw_text_anchored(
+            &mut d,
+            &font,
+            help.line_refs(),
+            Vector2::new(SCR_W as f32 / 2.0, SCR_H as f32 - 8.0),
+            24.0,
+            &text_config,
+        );
+    }
+    Ok(())
+}